@@ -5,7 +5,14 @@
 //! - Vocabulary-agnostic (works with any BPE merge table)
 //! - Returns byte ranges for precise visual mapping
 
-use rustc_hash::FxHashMap;
+use aho_corasick::{AhoCorasick, MatchKind};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use fancy_regex::Regex as FancyRegex;
+use regex::Regex;
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, VecDeque};
+use std::sync::Mutex;
 
 /// A raw token before visual metadata is attached.
 #[derive(Debug, Clone)]
@@ -16,6 +23,9 @@ pub struct RawToken {
     pub byte_end: usize,
 }
 
+/// Default capacity of a tokenizer's merge cache (see `MergeCache`).
+const DEFAULT_MERGE_CACHE_CAPACITY: usize = 4096;
+
 /// A BPE tokenizer loaded with a specific vocabulary.
 pub struct BpeTokenizer {
     /// Token string → token ID
@@ -26,8 +36,151 @@ pub struct BpeTokenizer {
     merges: FxHashMap<(Vec<u8>, Vec<u8>), usize>,
     /// Special tokens (e.g. <|endoftext|>)
     special_tokens: FxHashMap<String, u32>,
+    /// Leftmost-longest-match automaton over `special_tokens`'s surface
+    /// strings, rebuilt whenever `special_tokens` changes. `None` when
+    /// there are no special tokens. Makes `split_special_tokens` a single
+    /// linear pass over the input regardless of how many special tokens
+    /// are registered, and guarantees the longest token wins when two
+    /// special tokens start at the same position (e.g. `<|im_start|>`
+    /// vs. a hypothetical `<|im_start|>x` that extends it).
+    special_automaton: Option<(AhoCorasick, Vec<(String, u32)>)>,
     /// Total vocabulary size
     vocab_size: usize,
+    /// GPT-style pre-tokenization pattern. When set, text is split into
+    /// spans by this regex before BPE merging runs, so merges never cross
+    /// a whitespace/word/punctuation boundary. `None` preserves the
+    /// original whole-chunk merge behavior.
+    pre_tokenizer: Option<Regex>,
+    /// Real GPT-style pre-tokenization pattern (e.g. cl100k's
+    /// `(?i:'s|'t|...)|[^\r\n\p{L}\p{N}]?\p{L}+|...`), which relies on
+    /// case-insensitive groups and negative lookahead that the `regex`
+    /// crate can't express. When set, takes priority over `pre_tokenizer`
+    /// for `Chunk::Text` fragments; `None` preserves today's behavior.
+    pat: Option<FancyRegex>,
+    /// Probability of skipping each candidate merge during encoding, for
+    /// BPE dropout. Only sampled via `encode_with_seed`.
+    dropout: Option<f32>,
+    /// Bounded cache from pre-token span bytes to the resulting token ID
+    /// sequence, so re-tokenizing an unchanged word (the common case on
+    /// a single keystroke) skips the merge loop entirely.
+    cache: Mutex<MergeCache>,
+    /// Optional token id → normalized rarity (0.0 = most common, 1.0 =
+    /// rarest), derived from real corpus frequency or merge rank. When
+    /// absent, callers fall back to the `id / vocab_size` estimate.
+    rarity_table: Option<FxHashMap<u32, f32>>,
+}
+
+/// Bounded least-recently-used cache of pre-token spans to token ID
+/// sequences, with hit/miss counters for throughput reporting.
+struct MergeCache {
+    capacity: usize,
+    entries: FxHashMap<Vec<u8>, Vec<u32>>,
+    /// Usage order, oldest first. Linear-scanned on touch, which is fine
+    /// at the cache's intended "a few thousand entries" scale.
+    order: VecDeque<Vec<u8>>,
+    hits: u64,
+    misses: u64,
+}
+
+impl MergeCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: FxHashMap::default(),
+            order: VecDeque::new(),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    fn get(&mut self, key: &[u8]) -> Option<Vec<u32>> {
+        match self.entries.get(key) {
+            Some(ids) => {
+                let ids = ids.clone();
+                self.touch(key);
+                self.hits += 1;
+                Some(ids)
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    fn insert(&mut self, key: Vec<u8>, ids: Vec<u32>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.contains_key(&key) {
+            self.touch(&key);
+            self.entries.insert(key, ids);
+            return;
+        }
+        if self.entries.len() >= self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.order.push_back(key.clone());
+        self.entries.insert(key, ids);
+    }
+
+    fn touch(&mut self, key: &[u8]) {
+        if let Some(pos) = self.order.iter().position(|k| k.as_slice() == key) {
+            let k = self.order.remove(pos).expect("position was just found");
+            self.order.push_back(k);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+        self.hits = 0;
+        self.misses = 0;
+    }
+}
+
+/// Snapshot of a tokenizer's merge-cache throughput, for display in the
+/// WASM front-end (e.g. "cache hit rate: 94%").
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub len: usize,
+    pub capacity: usize,
+}
+
+impl CacheStats {
+    pub fn hit_rate(&self) -> f32 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            0.0
+        } else {
+            self.hits as f32 / total as f32
+        }
+    }
+}
+
+impl Clone for BpeTokenizer {
+    /// Clones the vocabulary and settings but starts with a fresh, empty
+    /// merge cache (the cache holds a `Mutex` and isn't itself `Clone`).
+    fn clone(&self) -> Self {
+        let capacity = self.cache.lock().unwrap().capacity;
+        Self {
+            encoder: self.encoder.clone(),
+            decoder: self.decoder.clone(),
+            merges: self.merges.clone(),
+            special_tokens: self.special_tokens.clone(),
+            special_automaton: self.special_automaton.clone(),
+            vocab_size: self.vocab_size,
+            pre_tokenizer: self.pre_tokenizer.clone(),
+            pat: self.pat.clone(),
+            dropout: self.dropout,
+            cache: Mutex::new(MergeCache::new(capacity)),
+            rarity_table: self.rarity_table.clone(),
+        }
+    }
 }
 
 impl BpeTokenizer {
@@ -47,21 +200,190 @@ impl BpeTokenizer {
             .map(|(i, pair)| (pair, i))
             .collect();
 
+        let special_automaton = build_special_automaton(&special_tokens);
+
         Self {
             encoder,
             decoder,
             merges: merge_map,
             special_tokens,
+            special_automaton,
             vocab_size,
+            pre_tokenizer: None,
+            pat: None,
+            dropout: None,
+            cache: Mutex::new(MergeCache::new(DEFAULT_MERGE_CACHE_CAPACITY)),
+            rarity_table: None,
         }
     }
 
+    /// Parse a `.tiktoken`-format vocabulary: one `<base64 token> <rank>`
+    /// pair per line, as shipped by OpenAI for cl100k_base/o200k_base/etc.
+    ///
+    /// `.tiktoken` files carry only ranks, not an explicit merge list, so
+    /// `merges` is reconstructed by replaying the BPE merge loop over each
+    /// multi-byte token's raw bytes (see `recover_pair`) — the same trick
+    /// tiktoken itself documents for recovering `mergeable_ranks` as an
+    /// ordered merge table.
+    pub fn from_tiktoken(
+        data: &str,
+        special_tokens: FxHashMap<String, u32>,
+    ) -> Result<Self, TiktokenLoadError> {
+        let mut encoder: FxHashMap<Vec<u8>, u32> = FxHashMap::default();
+        for line in data.lines() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut parts = line.split_whitespace();
+            let token_b64 = parts
+                .next()
+                .ok_or_else(|| TiktokenLoadError::Parse(format!("malformed line: {line}")))?;
+            let rank_str = parts
+                .next()
+                .ok_or_else(|| TiktokenLoadError::Parse(format!("malformed line: {line}")))?;
+            let bytes = STANDARD
+                .decode(token_b64)
+                .map_err(|e| TiktokenLoadError::Base64(e.to_string()))?;
+            let rank: u32 = rank_str
+                .parse()
+                .map_err(|_| TiktokenLoadError::Parse(format!("bad rank in line: {line}")))?;
+            encoder.insert(bytes, rank);
+        }
+
+        let merges = reconstruct_merges(&encoder);
+        Ok(Self::new(encoder, merges, special_tokens))
+    }
+
+    /// Override the merge cache's capacity (default a few thousand
+    /// entries). A capacity of `0` disables caching.
+    pub fn with_cache_capacity(mut self, capacity: usize) -> Self {
+        self.cache = Mutex::new(MergeCache::new(capacity));
+        self
+    }
+
+    /// Clear the merge cache, e.g. when switching to a different `VocabId`
+    /// so stale entries from the old vocabulary can't leak in.
+    pub fn clear_cache(&self) {
+        self.cache.lock().unwrap().clear();
+    }
+
+    /// Current cache hit/miss counters, for throughput reporting.
+    pub fn cache_stats(&self) -> CacheStats {
+        let cache = self.cache.lock().unwrap();
+        CacheStats {
+            hits: cache.hits,
+            misses: cache.misses,
+            len: cache.entries.len(),
+            capacity: cache.capacity,
+        }
+    }
+
+    /// Attach a pre-tokenization pattern: text is split into spans by this
+    /// regex first, and BPE merges run independently within each span.
+    pub fn with_pre_tokenizer(mut self, pattern: Regex) -> Self {
+        self.pre_tokenizer = Some(pattern);
+        self
+    }
+
+    /// Attach the real GPT-style split pattern (see `pat` field doc),
+    /// which needs lookaround `regex` can't do. Takes priority over
+    /// `with_pre_tokenizer` when both are set.
+    pub fn with_pat(mut self, pattern: FancyRegex) -> Self {
+        self.pat = Some(pattern);
+        self
+    }
+
+    /// Enable BPE dropout: during merging, each candidate merge is randomly
+    /// skipped with probability `p`, producing coarser, alternate
+    /// segmentations. Has no effect on plain `encode` (which is always
+    /// deterministic) — use `encode_with_seed` to actually sample dropout.
+    pub fn with_dropout(mut self, dropout: f32) -> Self {
+        self.dropout = Some(dropout);
+        self
+    }
+
     pub fn vocab_size(&self) -> usize {
         self.vocab_size
     }
 
-    /// Encode text into raw tokens with byte positions.
+    /// Attach a measured rarity table (see `rarity_table` field doc),
+    /// e.g. loaded via `VocabRegistry::register_rarity_table`.
+    pub fn with_rarity_table(mut self, table: FxHashMap<u32, f32>) -> Self {
+        self.rarity_table = Some(table);
+        self
+    }
+
+    /// Replace the rarity table on an already-constructed tokenizer
+    /// (used by the registry to attach a table after a vocab is loaded).
+    pub fn set_rarity_table(&mut self, table: FxHashMap<u32, f32>) {
+        self.rarity_table = Some(table);
+    }
+
+    /// Measured rarity for `id`, if a rarity table was loaded and it
+    /// covers this id. `None` means "fall back to the id-ratio estimate".
+    pub fn rarity_for(&self, id: u32) -> Option<f32> {
+        self.rarity_table.as_ref()?.get(&id).copied()
+    }
+
+    /// Iterate over every token in the vocabulary as `(id, decoded text)`,
+    /// including special tokens. Used by fuzzy search to enumerate
+    /// candidates without requiring a separate token list.
+    pub fn tokens(&self) -> impl Iterator<Item = (u32, String)> + '_ {
+        // Special tokens passed to `new` aren't in `decoder`; only ones
+        // added later via `add_special_token`/`assign_special` are.
+        let specials = self
+            .special_tokens
+            .iter()
+            .filter(move |&(_, id)| !self.decoder.contains_key(id))
+            .map(|(token, &id)| (id, token.clone()));
+        self.decoder
+            .iter()
+            .map(|(&id, bytes)| (id, String::from_utf8_lossy(bytes).to_string()))
+            .chain(specials)
+    }
+
+    /// Register a new special token under `id`, e.g. to fill an unused
+    /// reserved slot (`<|im_start|>`, a custom control token, ...).
+    /// Special tokens are matched atomically during encoding and never
+    /// split by BPE merges.
+    pub fn add_special_token(&mut self, token: impl Into<String>, id: u32) {
+        let token = token.into();
+        self.decoder.insert(id, token.as_bytes().to_vec());
+        self.special_tokens.insert(token, id);
+        self.vocab_size = self.encoder.len() + self.special_tokens.len();
+        self.special_automaton = build_special_automaton(&self.special_tokens);
+    }
+
+    /// Reassign an existing special token's surface string to `new`
+    /// without disturbing its numeric ID, so chat-format users can adapt
+    /// a base vocab to their own control-token scheme. Returns the token's
+    /// ID on success, or `None` if `old` wasn't a registered special token.
+    pub fn assign_special(&mut self, old: &str, new: &str) -> Option<u32> {
+        let id = self.special_tokens.remove(old)?;
+        self.decoder.insert(id, new.as_bytes().to_vec());
+        self.special_tokens.insert(new.to_string(), id);
+        self.special_automaton = build_special_automaton(&self.special_tokens);
+        Some(id)
+    }
+
+    /// Encode text into raw tokens with byte positions. Deterministic: if
+    /// dropout is configured it is not applied here, since there is no seed
+    /// to sample it from. Use `encode_with_seed` for dropout sampling.
     pub fn encode(&self, text: &str) -> Vec<RawToken> {
+        self.encode_internal(text, None)
+    }
+
+    /// Encode text, sampling BPE dropout (if configured via
+    /// `with_dropout`) from a RNG seeded with `seed`. The same seed and
+    /// input always reproduce the same segmentation; byte offsets stay
+    /// accurate regardless of which merges dropout skips.
+    pub fn encode_with_seed(&self, text: &str, seed: u64) -> Vec<RawToken> {
+        let mut rng = Rng::new(seed);
+        self.encode_internal(text, Some(&mut rng))
+    }
+
+    fn encode_internal(&self, text: &str, mut rng: Option<&mut Rng>) -> Vec<RawToken> {
         if text.is_empty() {
             return Vec::new();
         }
@@ -85,22 +407,17 @@ impl BpeTokenizer {
                     byte_offset += byte_len;
                 }
                 Chunk::Text(s) => {
-                    let tokens = self.bpe_encode_chunk(s.as_bytes());
-                    for token_bytes in tokens {
-                        let byte_len = token_bytes.len();
-                        let id = self
-                            .encoder
-                            .get(&token_bytes)
-                            .copied()
-                            .unwrap_or(0);
-                        let text = String::from_utf8_lossy(&token_bytes).to_string();
-                        result.push(RawToken {
-                            id,
-                            text,
-                            byte_start: byte_offset,
-                            byte_end: byte_offset + byte_len,
-                        });
-                        byte_offset += byte_len;
+                    if let Some(pattern) = &self.pat {
+                        let matches = pattern
+                            .find_iter(s)
+                            .filter_map(|m| m.ok())
+                            .map(|m| (m.start(), m.end()));
+                        self.encode_matched_spans(s, matches, &mut byte_offset, &mut result, rng.as_deref_mut());
+                    } else if let Some(pattern) = &self.pre_tokenizer {
+                        let matches = pattern.find_iter(s).map(|m| (m.start(), m.end()));
+                        self.encode_matched_spans(s, matches, &mut byte_offset, &mut result, rng.as_deref_mut());
+                    } else {
+                        self.encode_fragment(s, &mut byte_offset, &mut result, rng.as_deref_mut());
                     }
                 }
             }
@@ -109,6 +426,164 @@ impl BpeTokenizer {
         result
     }
 
+    /// Walk `matches` (byte ranges into `s`, in order) and BPE-encode each
+    /// matched span plus any unmatched gap between them, so every byte of
+    /// `s` ends up in some token regardless of how narrow the pattern is.
+    /// Shared by both `pre_tokenizer` and `pat`, which only differ in how
+    /// they produce match ranges.
+    fn encode_matched_spans(
+        &self,
+        s: &str,
+        matches: impl Iterator<Item = (usize, usize)>,
+        byte_offset: &mut usize,
+        result: &mut Vec<RawToken>,
+        mut rng: Option<&mut Rng>,
+    ) {
+        let mut last_end = 0usize;
+        for (start, end) in matches {
+            if start > last_end {
+                self.encode_fragment(&s[last_end..start], byte_offset, result, rng.as_deref_mut());
+            }
+            self.encode_fragment(&s[start..end], byte_offset, result, rng.as_deref_mut());
+            last_end = end;
+        }
+        if last_end < s.len() {
+            self.encode_fragment(&s[last_end..], byte_offset, result, rng.as_deref_mut());
+        }
+    }
+
+    /// BPE-encode a single pre-tokenized span and append its tokens to
+    /// `result`, advancing `byte_offset` past it.
+    fn encode_fragment(
+        &self,
+        span: &str,
+        byte_offset: &mut usize,
+        result: &mut Vec<RawToken>,
+        rng: Option<&mut Rng>,
+    ) {
+        // Dropout makes the merge outcome non-deterministic per call, so a
+        // span can't be cached while it's in effect.
+        let cacheable = self.dropout.is_none() || rng.is_none();
+
+        if cacheable {
+            if let Some(ids) = self.cache.lock().unwrap().get(span.as_bytes()) {
+                for id in ids {
+                    self.push_cached_token(id, byte_offset, result);
+                }
+                return;
+            }
+        }
+
+        let tokens = self.bpe_encode_chunk(span.as_bytes(), rng);
+        let mut ids = Vec::with_capacity(tokens.len());
+        for token_bytes in tokens {
+            let byte_len = token_bytes.len();
+            let id = self.encoder.get(&token_bytes).copied().unwrap_or(0);
+            let text = String::from_utf8_lossy(&token_bytes).to_string();
+            result.push(RawToken {
+                id,
+                text,
+                byte_start: *byte_offset,
+                byte_end: *byte_offset + byte_len,
+            });
+            *byte_offset += byte_len;
+            ids.push(id);
+        }
+
+        if cacheable {
+            self.cache.lock().unwrap().insert(span.as_bytes().to_vec(), ids);
+        }
+    }
+
+    /// Reconstruct a `RawToken` for a cached id, looking up its bytes via
+    /// the decoder so the cache only needs to store the id sequence.
+    fn push_cached_token(&self, id: u32, byte_offset: &mut usize, result: &mut Vec<RawToken>) {
+        let bytes = self.decoder.get(&id).cloned().unwrap_or_default();
+        let byte_len = bytes.len();
+        let text = String::from_utf8_lossy(&bytes).to_string();
+        result.push(RawToken {
+            id,
+            text,
+            byte_start: *byte_offset,
+            byte_end: *byte_offset + byte_len,
+        });
+        *byte_offset += byte_len;
+    }
+
+    /// Count how many tokens `text` would produce under `encode`, without
+    /// allocating the per-piece `String`/`RawToken` that `encode` builds —
+    /// for UI token-budget indicators that only need the number.
+    pub fn count_tokens(&self, text: &str) -> usize {
+        if text.is_empty() {
+            return 0;
+        }
+
+        self.split_special_tokens(text)
+            .into_iter()
+            .map(|chunk| match chunk {
+                Chunk::Special(_, _) => 1,
+                Chunk::Text(s) => self.count_fragment_tokens(s),
+            })
+            .sum()
+    }
+
+    /// Count-only counterpart of the `Chunk::Text` branch in
+    /// `encode_internal`: same pre-tokenizer priority (`pat` over
+    /// `pre_tokenizer` over whole-chunk), but tallying piece counts
+    /// instead of building `RawToken`s.
+    fn count_fragment_tokens(&self, s: &str) -> usize {
+        if let Some(pattern) = &self.pat {
+            let matches = pattern
+                .find_iter(s)
+                .filter_map(|m| m.ok())
+                .map(|m| (m.start(), m.end()));
+            self.count_matched_spans(s, matches)
+        } else if let Some(pattern) = &self.pre_tokenizer {
+            let matches = pattern.find_iter(s).map(|m| (m.start(), m.end()));
+            self.count_matched_spans(s, matches)
+        } else {
+            self.count_span_tokens(s)
+        }
+    }
+
+    fn count_matched_spans(&self, s: &str, matches: impl Iterator<Item = (usize, usize)>) -> usize {
+        let mut last_end = 0usize;
+        let mut total = 0usize;
+        for (start, end) in matches {
+            if start > last_end {
+                total += self.count_span_tokens(&s[last_end..start]);
+            }
+            total += self.count_span_tokens(&s[start..end]);
+            last_end = end;
+        }
+        if last_end < s.len() {
+            total += self.count_span_tokens(&s[last_end..]);
+        }
+        total
+    }
+
+    /// Count the pieces `span` BPE-encodes to, going through the merge
+    /// cache exactly like `encode_fragment` (and populating it on a miss)
+    /// so a `count_tokens` call warms the cache for a later `encode` the
+    /// same way the reverse order would.
+    fn count_span_tokens(&self, span: &str) -> usize {
+        if span.is_empty() {
+            return 0;
+        }
+        if let Some(ids) = self.cache.lock().unwrap().get(span.as_bytes()) {
+            return ids.len();
+        }
+
+        let tokens = self.bpe_encode_chunk(span.as_bytes(), None);
+        let count = tokens.len();
+        let ids: Vec<u32> = tokens
+            .into_iter()
+            .map(|token_bytes| self.encoder.get(&token_bytes).copied().unwrap_or(0))
+            .collect();
+        self.cache.lock().unwrap().insert(span.as_bytes().to_vec(), ids);
+        count
+    }
+
     /// Decode a sequence of token IDs back to text.
     pub fn decode(&self, ids: &[u32]) -> String {
         let bytes: Vec<u8> = ids
@@ -124,148 +599,478 @@ impl BpeTokenizer {
     }
 
     /// Core BPE algorithm: repeatedly merge the highest-priority pair.
-    fn bpe_encode_chunk(&self, input: &[u8]) -> Vec<Vec<u8>> {
+    /// When `rng` is provided and dropout is configured, each candidate
+    /// merge is independently skipped with probability `dropout` before
+    /// the best one is chosen, yielding coarser alternate segmentations.
+    ///
+    /// Ported from tiktoken's rank-cache approach rather than the naive
+    /// rescan-and-clone-everything loop: `parts` holds one `(byte_start,
+    /// rank)` entry per current piece boundary, where `rank` is the merge
+    /// rank of the pair starting at that boundary (`Rank::MAX` == no
+    /// merge / no right neighbor). A sentinel final entry closes the last
+    /// piece. Each merge only recomputes the two ranks whose right
+    /// neighbor just changed, turning an O(pieces) rescan-and-clone into
+    /// O(1) local work plus an O(pieces) min-scan.
+    fn bpe_encode_chunk(&self, input: &[u8], mut rng: Option<&mut Rng>) -> Vec<Vec<u8>> {
         if input.is_empty() {
             return Vec::new();
         }
 
-        // Start with each byte as its own piece
-        let mut pieces: Vec<Vec<u8>> = input.iter().map(|&b| vec![b]).collect();
+        let mut parts: Vec<(usize, usize)> =
+            (0..=input.len()).map(|byte_start| (byte_start, usize::MAX)).collect();
+
+        for i in 0..parts.len().saturating_sub(2) {
+            parts[i].1 = self.pair_rank(input, &parts, i, rng.as_deref_mut());
+        }
 
         loop {
-            if pieces.len() < 2 {
+            if parts.len() < 3 {
                 break;
             }
 
-            // Find the pair with the lowest merge rank
-            let mut best_rank = usize::MAX;
-            let mut best_idx = None;
-
-            for i in 0..pieces.len() - 1 {
-                let pair = (pieces[i].clone(), pieces[i + 1].clone());
-                if let Some(&rank) = self.merges.get(&pair) {
-                    if rank < best_rank {
-                        best_rank = rank;
-                        best_idx = Some(i);
-                    }
+            let mut min_rank = usize::MAX;
+            let mut min_idx = 0;
+            for (i, &(_, rank)) in parts[..parts.len() - 1].iter().enumerate() {
+                if rank < min_rank {
+                    min_rank = rank;
+                    min_idx = i;
                 }
             }
 
-            match best_idx {
-                Some(idx) => {
-                    // Merge the pair
-                    let mut merged = pieces[idx].clone();
-                    merged.extend_from_slice(&pieces[idx + 1]);
-                    pieces[idx] = merged;
-                    pieces.remove(idx + 1);
-                }
-                None => break, // No more merges possible
+            if min_rank == usize::MAX {
+                break; // No more merges possible
             }
+
+            // Merge the pair at (min_idx, min_idx + 1) by dropping the
+            // boundary between them; the piece at min_idx now spans what
+            // used to be two pieces.
+            parts.remove(min_idx + 1);
+
+            // Only the entries whose right neighbor changed need new
+            // ranks: the merged piece itself, and (if any) its left
+            // neighbor.
+            if min_idx > 0 {
+                parts[min_idx - 1].1 = self.pair_rank(input, &parts, min_idx - 1, rng.as_deref_mut());
+            }
+            parts[min_idx].1 = self.pair_rank(input, &parts, min_idx, rng.as_deref_mut());
         }
 
-        pieces
+        parts
+            .windows(2)
+            .map(|w| input[w[0].0..w[1].0].to_vec())
+            .collect()
+    }
+
+    /// Merge rank of the pair `(piece at parts[i], piece at parts[i+1])`,
+    /// given piece boundaries `parts[i].0..parts[i+1].0` and
+    /// `parts[i+1].0..parts[i+2].0`. Returns `Rank::MAX` (`usize::MAX`) if
+    /// there's no right neighbor, no registered merge for that pair, or
+    /// (under dropout) this merge was randomly skipped.
+    fn pair_rank(
+        &self,
+        input: &[u8],
+        parts: &[(usize, usize)],
+        i: usize,
+        mut rng: Option<&mut Rng>,
+    ) -> usize {
+        if i + 2 >= parts.len() {
+            return usize::MAX;
+        }
+        let left = &input[parts[i].0..parts[i + 1].0];
+        let right = &input[parts[i + 1].0..parts[i + 2].0];
+        match self.merges.get(&(left.to_vec(), right.to_vec())) {
+            Some(&rank) => {
+                if let (Some(p), Some(r)) = (self.dropout, rng.as_mut()) {
+                    if r.next_f32() < p {
+                        return usize::MAX;
+                    }
+                }
+                rank
+            }
+            None => usize::MAX,
+        }
     }
 
-    /// Split text on special token boundaries.
+    /// Split text on special token boundaries via `special_automaton`, in
+    /// a single forward pass over non-overlapping leftmost-longest
+    /// matches.
     fn split_special_tokens<'a>(&self, text: &'a str) -> Vec<Chunk<'a>> {
-        if self.special_tokens.is_empty() {
+        let Some((automaton, list)) = &self.special_automaton else {
             return vec![Chunk::Text(text)];
-        }
+        };
 
         let mut chunks = Vec::new();
-        let mut remaining = text;
+        let mut last_end = 0usize;
 
-        while !remaining.is_empty() {
-            let mut earliest_match: Option<(&str, u32, usize)> = None;
-
-            for (token, &id) in &self.special_tokens {
-                if let Some(pos) = remaining.find(token.as_str()) {
-                    if earliest_match.is_none() || pos < earliest_match.unwrap().2 {
-                        earliest_match = Some((token.as_str(), id, pos));
-                    }
-                }
+        for mat in automaton.find_iter(text) {
+            if mat.start() > last_end {
+                chunks.push(Chunk::Text(&text[last_end..mat.start()]));
             }
+            let (token, id) = &list[mat.pattern().as_usize()];
+            chunks.push(Chunk::Special(token.clone(), *id));
+            last_end = mat.end();
+        }
 
-            match earliest_match {
-                Some((token, id, pos)) => {
-                    if pos > 0 {
-                        chunks.push(Chunk::Text(&remaining[..pos]));
-                    }
-                    chunks.push(Chunk::Special(
-                        token.to_string(),
-                        id,
-                    ));
-                    remaining = &remaining[pos + token.len()..];
-                }
-                None => {
-                    chunks.push(Chunk::Text(remaining));
-                    break;
-                }
-            }
+        if last_end < text.len() {
+            chunks.push(Chunk::Text(&text[last_end..]));
         }
 
         chunks
     }
 }
 
+/// Build a leftmost-longest-match automaton over `special_tokens`'s
+/// surface strings, paired with the `(token, id)` list indexed by the
+/// automaton's internal pattern IDs. `None` if there are no special
+/// tokens to match.
+fn build_special_automaton(special_tokens: &FxHashMap<String, u32>) -> Option<(AhoCorasick, Vec<(String, u32)>)> {
+    if special_tokens.is_empty() {
+        return None;
+    }
+
+    let list: Vec<(String, u32)> = special_tokens.iter().map(|(k, &v)| (k.clone(), v)).collect();
+    let patterns: Vec<&str> = list.iter().map(|(k, _)| k.as_str()).collect();
+    let automaton = AhoCorasick::builder()
+        .match_kind(MatchKind::LeftmostLongest)
+        .build(&patterns)
+        .expect("building aho-corasick automaton over special tokens should not fail");
+    Some((automaton, list))
+}
+
 enum Chunk<'a> {
     Text(&'a str),
     Special(String, u32),
 }
 
-/// Build a simple byte-level BPE vocabulary from scratch.
-/// This creates a base vocab of 256 single-byte tokens,
-/// then learns `num_merges` merge pairs from the training text.
-pub fn train_bpe(text: &str, num_merges: usize) -> BpeTokenizer {
-    // Base vocabulary: every possible byte
-    let mut encoder: FxHashMap<Vec<u8>, u32> = FxHashMap::default();
-    for i in 0u32..256 {
-        encoder.insert(vec![i as u8], i);
+/// Errors from parsing a `.tiktoken`-format vocabulary via
+/// `BpeTokenizer::from_tiktoken`.
+#[derive(Debug)]
+pub enum TiktokenLoadError {
+    Base64(String),
+    Parse(String),
+}
+
+impl std::fmt::Display for TiktokenLoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TiktokenLoadError::Base64(e) => write!(f, "malformed base64 token: {e}"),
+            TiktokenLoadError::Parse(msg) => write!(f, "malformed .tiktoken line: {msg}"),
+        }
     }
+}
 
-    let bytes = text.as_bytes();
-    let mut pieces: Vec<Vec<u8>> = bytes.iter().map(|&b| vec![b]).collect();
-    let mut merges: Vec<(Vec<u8>, Vec<u8>)> = Vec::with_capacity(num_merges);
-    let mut next_id = 256u32;
+impl std::error::Error for TiktokenLoadError {}
 
-    for _ in 0..num_merges {
-        // Count all adjacent pairs
-        let mut pair_counts: FxHashMap<(Vec<u8>, Vec<u8>), usize> = FxHashMap::default();
-        for window in pieces.windows(2) {
-            let pair = (window[0].clone(), window[1].clone());
-            *pair_counts.entry(pair).or_insert(0) += 1;
+/// Reconstruct an ordered merge list from a `.tiktoken` rank table: for
+/// every multi-byte token, in increasing rank order, recover the pair of
+/// shorter vocabulary entries whose merge produced it.
+fn reconstruct_merges(encoder: &FxHashMap<Vec<u8>, u32>) -> Vec<(Vec<u8>, Vec<u8>)> {
+    let mut by_rank: Vec<(&Vec<u8>, u32)> = encoder.iter().map(|(bytes, &rank)| (bytes, rank)).collect();
+    by_rank.sort_by_key(|&(_, rank)| rank);
+
+    let mut merges = Vec::with_capacity(by_rank.len());
+    for (token, rank) in by_rank {
+        if token.len() < 2 {
+            continue;
+        }
+        if let Some(pair) = recover_pair(encoder, token, rank) {
+            merges.push(pair);
         }
+    }
+    merges
+}
 
-        // Find most frequent pair
-        let best = pair_counts.into_iter().max_by_key(|&(_, count)| count);
+/// Replay the BPE merge loop over `token`'s individual bytes, using each
+/// candidate pair's own rank as priority but refusing any merge whose rank
+/// is `>= max_rank` (the rank `token` itself was assigned) — since `token`
+/// was produced at step `max_rank`, nothing that only merges at or after
+/// that step could have fed into it. The two parts left standing at the
+/// end are the pair whose merge produces `token`.
+fn recover_pair(encoder: &FxHashMap<Vec<u8>, u32>, token: &[u8], max_rank: u32) -> Option<(Vec<u8>, Vec<u8>)> {
+    let mut parts: Vec<Vec<u8>> = token.iter().map(|&b| vec![b]).collect();
 
-        match best {
-            Some((pair, count)) if count >= 2 => {
-                let mut merged = pair.0.clone();
-                merged.extend_from_slice(&pair.1);
+    loop {
+        let mut min_idx = None;
+        let mut min_rank = None;
+        for i in 0..parts.len().saturating_sub(1) {
+            let mut candidate = parts[i].clone();
+            candidate.extend_from_slice(&parts[i + 1]);
+            if let Some(&rank) = encoder.get(&candidate) {
+                if min_rank.map_or(true, |r| rank < r) {
+                    min_idx = Some(i);
+                    min_rank = Some(rank);
+                }
+            }
+        }
 
-                encoder.insert(merged.clone(), next_id);
-                merges.push((pair.0.clone(), pair.1.clone()));
-                next_id += 1;
+        let (idx, rank) = match (min_idx, min_rank) {
+            (Some(i), Some(r)) => (i, r),
+            _ => break,
+        };
+        if rank >= max_rank {
+            break;
+        }
 
-                // Apply this merge to all pieces
-                let mut new_pieces = Vec::with_capacity(pieces.len());
-                let mut i = 0;
-                while i < pieces.len() {
-                    if i + 1 < pieces.len() && pieces[i] == pair.0 && pieces[i + 1] == pair.1 {
-                        new_pieces.push(merged.clone());
-                        i += 2;
-                    } else {
-                        new_pieces.push(pieces[i].clone());
-                        i += 1;
-                    }
+        let mut merged = parts[idx].clone();
+        merged.extend_from_slice(&parts[idx + 1]);
+        parts.splice(idx..=idx + 1, [merged]);
+    }
+
+    if parts.len() == 2 {
+        Some((parts[0].clone(), parts[1].clone()))
+    } else {
+        None
+    }
+}
+
+/// Minimal seedable PRNG (SplitMix64) used to sample BPE dropout.
+/// Deterministic and dependency-free; not suitable for cryptographic use.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // Avoid the degenerate all-zero state.
+        Self(seed ^ 0x9E3779B97F4A7C15)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform float in `[0, 1)`.
+    fn next_f32(&mut self) -> f32 {
+        ((self.next_u64() >> 40) as f32) / ((1u64 << 24) as f32)
+    }
+}
+
+/// A candidate merge waiting in a trainer's priority queue, ordered by
+/// count (highest first); ties are broken by pair so training is
+/// deterministic regardless of `HashMap` iteration order. Shared by
+/// `train_bpe` and `vocab::BpeTrainer::train`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct MergeCandidate {
+    pair: (u32, u32),
+    count: i64,
+}
+
+impl Ord for MergeCandidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.count
+            .cmp(&other.count)
+            .then_with(|| other.pair.cmp(&self.pair))
+    }
+}
+
+impl PartialOrd for MergeCandidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Count-and-merge core shared by `train_bpe` and `vocab::BpeTrainer::train`.
+///
+/// Tallies adjacent-pair counts and the set of word indices each pair
+/// occurs in, then repeatedly pops the globally best pair off a
+/// `BinaryHeap<MergeCandidate>`, re-validates its count against the live
+/// map (discarding stale entries left behind by earlier merges), and only
+/// walks the words that pair actually occurs in — turning an
+/// O(corpus · merges) rescan-and-rebuild into O(corpus + merges ·
+/// affected-words). Stops once `next_id` reaches `stop_at_next_id` or the
+/// best remaining pair's count drops below `min_count`.
+///
+/// `words`/`counts` are mutated in place (words are progressively merged);
+/// `encoder`/`id_to_bytes`/`next_id` gain one entry per merge.
+pub(crate) fn run_bpe_merge_loop(
+    words: &mut [Vec<u32>],
+    counts: &[i64],
+    encoder: &mut FxHashMap<Vec<u8>, u32>,
+    id_to_bytes: &mut FxHashMap<u32, Vec<u8>>,
+    next_id: &mut u32,
+    stop_at_next_id: u32,
+    min_count: i64,
+) -> Vec<(Vec<u8>, Vec<u8>)> {
+    let mut pair_counts: FxHashMap<(u32, u32), i64> = FxHashMap::default();
+    let mut pair_positions: FxHashMap<(u32, u32), FxHashSet<usize>> = FxHashMap::default();
+    for (wi, symbols) in words.iter().enumerate() {
+        for pair in symbols.windows(2) {
+            let key = (pair[0], pair[1]);
+            *pair_counts.entry(key).or_insert(0) += counts[wi];
+            pair_positions.entry(key).or_default().insert(wi);
+        }
+    }
+
+    let mut heap: BinaryHeap<MergeCandidate> = pair_counts
+        .iter()
+        .map(|(&pair, &count)| MergeCandidate { pair, count })
+        .collect();
+
+    let mut merges: Vec<(Vec<u8>, Vec<u8>)> = Vec::new();
+
+    while *next_id < stop_at_next_id {
+        let Some(top) = heap.pop() else { break };
+
+        // Stale entry: the live count has moved since this was pushed.
+        let live_count = *pair_counts.get(&top.pair).unwrap_or(&0);
+        if live_count != top.count {
+            continue;
+        }
+        if live_count < min_count {
+            break;
+        }
+
+        let (left, right) = top.pair;
+        let left_bytes = id_to_bytes[&left].clone();
+        let right_bytes = id_to_bytes[&right].clone();
+        let mut merged_bytes = left_bytes.clone();
+        merged_bytes.extend_from_slice(&right_bytes);
+
+        let new_id = *next_id;
+        *next_id += 1;
+        encoder.insert(merged_bytes.clone(), new_id);
+        id_to_bytes.insert(new_id, merged_bytes);
+        merges.push((left_bytes, right_bytes));
+
+        pair_counts.remove(&top.pair);
+        let positions = pair_positions.remove(&top.pair).unwrap_or_default();
+
+        for wi in positions {
+            let wc = counts[wi];
+            let word = &mut words[wi];
+            // Pairs whose count we touched while merging occurrences of
+            // (left, right) in this word. A pair can occur more than once
+            // in the same word (e.g. `(2, 2)` in `[0, 2, 1, 0, 2, 2, 2]`),
+            // so losing one occurrence doesn't mean `wi` stops containing
+            // it — membership in `pair_positions` is reconciled against the
+            // word's final state below, once, instead of on every local
+            // disappearance.
+            let mut touched_pairs: FxHashSet<(u32, u32)> = FxHashSet::default();
+            let mut i = 0;
+            while i + 1 < word.len() {
+                if word[i] != left || word[i + 1] != right {
+                    i += 1;
+                    continue;
+                }
+
+                if i > 0 {
+                    let before = (word[i - 1], word[i]);
+                    let count = {
+                        let c = pair_counts.entry(before).or_insert(0);
+                        *c -= wc;
+                        *c
+                    };
+                    // A decrement can leave this pair's only heap entry
+                    // stale (too high), which would make it invisible to
+                    // the `live_count != top.count` staleness check
+                    // forever even though it's still a live candidate —
+                    // push a fresh entry reflecting the current count.
+                    heap.push(MergeCandidate { pair: before, count });
+                    touched_pairs.insert(before);
+                }
+                if i + 2 < word.len() {
+                    let after = (word[i + 1], word[i + 2]);
+                    let count = {
+                        let c = pair_counts.entry(after).or_insert(0);
+                        *c -= wc;
+                        *c
+                    };
+                    heap.push(MergeCandidate { pair: after, count });
+                    touched_pairs.insert(after);
+                }
+
+                word[i] = new_id;
+                word.remove(i + 1);
+
+                if i > 0 {
+                    let new_before = (word[i - 1], word[i]);
+                    let count = *pair_counts
+                        .entry(new_before)
+                        .and_modify(|c| *c += wc)
+                        .or_insert(wc);
+                    heap.push(MergeCandidate {
+                        pair: new_before,
+                        count,
+                    });
+                    touched_pairs.insert(new_before);
+                }
+                if i + 1 < word.len() {
+                    let new_after = (word[i], word[i + 1]);
+                    let count = *pair_counts
+                        .entry(new_after)
+                        .and_modify(|c| *c += wc)
+                        .or_insert(wc);
+                    heap.push(MergeCandidate {
+                        pair: new_after,
+                        count,
+                    });
+                    touched_pairs.insert(new_after);
+                }
+
+                i += 1;
+            }
+
+            // Reconcile `pair_positions` membership for every pair touched
+            // while processing this word, against the word's final state.
+            let still_present: FxHashSet<(u32, u32)> =
+                word.windows(2).map(|p| (p[0], p[1])).collect();
+            for pair in touched_pairs {
+                if still_present.contains(&pair) {
+                    pair_positions.entry(pair).or_default().insert(wi);
+                } else if let Some(set) = pair_positions.get_mut(&pair) {
+                    set.remove(&wi);
                 }
-                pieces = new_pieces;
             }
-            _ => break,
         }
     }
 
+    merges
+}
+
+/// Build a simple byte-level BPE vocabulary from scratch: a base vocab of
+/// 256 single-byte tokens, then up to `num_merges` learned merge pairs
+/// (training stops early once no pair occurs at least twice).
+///
+/// Counts whitespace-separated words once into a `HashMap<&str, u64>`
+/// rather than rescanning the whole corpus per merge; the merge loop
+/// itself is [`run_bpe_merge_loop`], the same one `vocab::BpeTrainer`
+/// uses.
+pub fn train_bpe(text: &str, num_merges: usize) -> BpeTokenizer {
+    let mut encoder: FxHashMap<Vec<u8>, u32> = FxHashMap::default();
+    for i in 0u32..256 {
+        encoder.insert(vec![i as u8], i);
+    }
+    let mut id_to_bytes: FxHashMap<u32, Vec<u8>> = encoder.iter().map(|(b, &id)| (id, b.clone())).collect();
+
+    let mut word_counts: FxHashMap<&str, u64> = FxHashMap::default();
+    for word in text.split_whitespace() {
+        *word_counts.entry(word).or_insert(0) += 1;
+    }
+
+    let mut sorted_words: Vec<(&str, u64)> = word_counts.into_iter().collect();
+    sorted_words.sort_unstable_by(|a, b| a.0.cmp(b.0));
+
+    let mut words: Vec<Vec<u32>> = Vec::with_capacity(sorted_words.len());
+    let mut counts: Vec<i64> = Vec::with_capacity(sorted_words.len());
+    for (w, c) in sorted_words {
+        words.push(w.bytes().map(|b| b as u32).collect());
+        counts.push(c as i64);
+    }
+
+    let mut next_id = 256u32;
+    let stop_at_next_id = next_id + num_merges as u32;
+    let merges = run_bpe_merge_loop(
+        &mut words,
+        &counts,
+        &mut encoder,
+        &mut id_to_bytes,
+        &mut next_id,
+        stop_at_next_id,
+        2,
+    );
+
     BpeTokenizer::new(encoder, merges, FxHashMap::default())
 }
 
@@ -326,6 +1131,23 @@ mod tests {
         assert_eq!(tokens[0].byte_end, 3);
     }
 
+    #[test]
+    fn test_count_tokens_matches_encode_len() {
+        let tok = make_simple_tokenizer();
+        let texts = ["the thing", "thing", "", "ab"];
+        for text in texts {
+            assert_eq!(tok.count_tokens(text), tok.encode(text).len());
+        }
+    }
+
+    #[test]
+    fn test_count_tokens_with_special_tokens() {
+        let mut tok = make_simple_tokenizer();
+        tok.add_special_token("<|endoftext|>", 9000);
+        let text = "the<|endoftext|>thing";
+        assert_eq!(tok.count_tokens(text), tok.encode(text).len());
+    }
+
     #[test]
     fn test_empty() {
         let tok = make_simple_tokenizer();
@@ -343,6 +1165,200 @@ mod tests {
         assert_eq!(decoded, input);
     }
 
+    #[test]
+    fn test_pre_tokenizer_splits_digits_from_words() {
+        let mut encoder: FxHashMap<Vec<u8>, u32> = FxHashMap::default();
+        for i in 0u32..256 {
+            encoder.insert(vec![i as u8], i);
+        }
+        encoder.insert(b"c1".to_vec(), 256);
+        let merges = vec![(b"c".to_vec(), b"1".to_vec())];
+        let tok = BpeTokenizer::new(encoder, merges, FxHashMap::default())
+            .with_pre_tokenizer(Regex::new(r" ?\p{L}+| ?\p{N}+|\s+").unwrap());
+
+        let tokens = tok.encode("abc123");
+        // A "c"+"1" merge exists, so only the letter/digit span split keeps
+        // "c" and "1" apart; the letters and digits must land in separate spans.
+        let texts: Vec<&str> = tokens.iter().map(|t| t.text.as_str()).collect();
+        assert!(texts.join("").as_str() == "abc123");
+        let letters_end = tokens.iter().position(|t| t.text == "1").unwrap();
+        assert!(tokens[..letters_end].iter().all(|t| t.text.chars().all(|c| c.is_alphabetic())));
+    }
+
+    #[test]
+    fn test_pat_splits_contraction_and_preserves_bytes() {
+        let mut encoder: FxHashMap<Vec<u8>, u32> = FxHashMap::default();
+        for i in 0u32..256 {
+            encoder.insert(vec![i as u8], i);
+        }
+        let gpt_pattern = FancyRegex::new(
+            r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+(?!\S)|\s+",
+        )
+        .unwrap();
+        // "n'" outranks "'t", so without the split "don't" would merge "n'".
+        encoder.insert(b"n'".to_vec(), 256);
+        encoder.insert(b"'t".to_vec(), 257);
+        let merges = vec![(b"n".to_vec(), b"'".to_vec()), (b"'".to_vec(), b"t".to_vec())];
+        let tok = BpeTokenizer::new(encoder, merges, FxHashMap::default()).with_pat(gpt_pattern);
+
+        let tokens = tok.encode("don't stop");
+        let texts: Vec<&str> = tokens.iter().map(|t| t.text.as_str()).collect();
+        assert_eq!(texts.join(""), "don't stop");
+        // "'t" must be its own span, never merged with "don".
+        assert!(texts.contains(&"'t"));
+    }
+
+    #[test]
+    fn test_pat_takes_priority_over_pre_tokenizer() {
+        let mut encoder: FxHashMap<Vec<u8>, u32> = FxHashMap::default();
+        for i in 0u32..256 {
+            encoder.insert(vec![i as u8], i);
+        }
+        // "b1" outranks "ab"/"12", so whole-chunk merging would form "b1".
+        encoder.insert(b"b1".to_vec(), 256);
+        encoder.insert(b"ab".to_vec(), 257);
+        encoder.insert(b"12".to_vec(), 258);
+        let merges = vec![
+            (b"b".to_vec(), b"1".to_vec()),
+            (b"a".to_vec(), b"b".to_vec()),
+            (b"1".to_vec(), b"2".to_vec()),
+        ];
+        let tok = BpeTokenizer::new(encoder, merges, FxHashMap::default())
+            .with_pre_tokenizer(Regex::new(r".+").unwrap())
+            .with_pat(FancyRegex::new(r"\p{L}+|\p{N}+|\s+|.").unwrap());
+
+        let tokens = tok.encode("ab12");
+        let texts: Vec<&str> = tokens.iter().map(|t| t.text.as_str()).collect();
+        assert_eq!(texts, vec!["ab", "12"]);
+    }
+
+    #[test]
+    fn test_dropout_reproducible_with_same_seed() {
+        let tok = make_simple_tokenizer().with_dropout(0.9);
+        let a = tok.encode_with_seed("thing", 42);
+        let b = tok.encode_with_seed("thing", 42);
+        let ids_a: Vec<u32> = a.iter().map(|t| t.id).collect();
+        let ids_b: Vec<u32> = b.iter().map(|t| t.id).collect();
+        assert_eq!(ids_a, ids_b);
+    }
+
+    #[test]
+    fn test_no_dropout_matches_plain_encode() {
+        let tok = make_simple_tokenizer();
+        let a = tok.encode("thing");
+        let b = tok.encode_with_seed("thing", 7);
+        let ids_a: Vec<u32> = a.iter().map(|t| t.id).collect();
+        let ids_b: Vec<u32> = b.iter().map(|t| t.id).collect();
+        assert_eq!(ids_a, ids_b);
+    }
+
+    #[test]
+    fn test_merge_cache_hits_on_repeat_word() {
+        let tok = make_simple_tokenizer();
+        tok.encode("the thing");
+        let stats_before = tok.cache_stats();
+        assert!(stats_before.misses > 0);
+
+        tok.encode("the thing");
+        let stats_after = tok.cache_stats();
+        assert!(stats_after.hits > stats_before.hits);
+    }
+
+    #[test]
+    fn test_clear_cache_resets_stats() {
+        let tok = make_simple_tokenizer();
+        tok.encode("the thing");
+        tok.clear_cache();
+        let stats = tok.cache_stats();
+        assert_eq!(stats.hits, 0);
+        assert_eq!(stats.misses, 0);
+        assert_eq!(stats.len, 0);
+    }
+
+    #[test]
+    fn test_zero_capacity_disables_cache() {
+        let tok = make_simple_tokenizer().with_cache_capacity(0);
+        tok.encode("the");
+        tok.encode("the");
+        let stats = tok.cache_stats();
+        assert_eq!(stats.len, 0);
+    }
+
+    #[test]
+    fn test_add_special_token_round_trips() {
+        let mut tok = make_simple_tokenizer();
+        tok.add_special_token("<|im_start|>", 9000);
+
+        let tokens = tok.encode("<|im_start|>the");
+        assert_eq!(tokens[0].id, 9000);
+        assert_eq!(tokens[0].text, "<|im_start|>");
+
+        let decoded = tok.decode(&[9000]);
+        assert_eq!(decoded, "<|im_start|>");
+    }
+
+    #[test]
+    fn test_assign_special_keeps_id() {
+        let mut tok = make_simple_tokenizer();
+        tok.add_special_token("<|endoftext|>", 9000);
+
+        let id = tok.assign_special("<|endoftext|>", "<|im_end|>").unwrap();
+        assert_eq!(id, 9000);
+
+        let tokens = tok.encode("<|im_end|>the");
+        assert_eq!(tokens[0].id, 9000);
+        assert_eq!(tokens[0].text, "<|im_end|>");
+        assert_eq!(tok.decode(&[9000]), "<|im_end|>");
+    }
+
+    #[test]
+    fn test_special_tokens_prefer_longest_match() {
+        let mut tok = make_simple_tokenizer();
+        tok.add_special_token("<|im_start|", 9000);
+        tok.add_special_token("<|im_start|extra|>", 9001);
+
+        let tokens = tok.encode("<|im_start|extra|>the");
+        assert_eq!(tokens[0].id, 9001);
+        assert_eq!(tokens[0].text, "<|im_start|extra|>");
+    }
+
+    #[test]
+    fn test_multiple_special_tokens_interleave_with_text() {
+        let mut tok = make_simple_tokenizer();
+        tok.add_special_token("<|fim_prefix|>", 9000);
+        tok.add_special_token("<|fim_suffix|>", 9001);
+
+        let tokens = tok.encode("<|fim_prefix|>the<|fim_suffix|>the");
+        let ids: Vec<u32> = tokens.iter().map(|t| t.id).collect();
+        assert_eq!(ids.first(), Some(&9000));
+        assert!(ids.contains(&9001));
+        assert_eq!(ids.last(), Some(&259)); // "the"
+    }
+
+    #[test]
+    fn test_assign_special_missing_returns_none() {
+        let mut tok = make_simple_tokenizer();
+        assert!(tok.assign_special("<|nope|>", "<|still_nope|>").is_none());
+    }
+
+    #[test]
+    fn test_from_tiktoken_reconstructs_merges() {
+        // "YQ==" = base64("a"), "Yg==" = base64("b"), "YWI=" = base64("ab")
+        let data = "YQ== 0\nYg== 1\nYWI= 2\n";
+        let tok = BpeTokenizer::from_tiktoken(data, FxHashMap::default()).unwrap();
+
+        let tokens = tok.encode("ab");
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].id, 2);
+        assert_eq!(tokens[0].text, "ab");
+    }
+
+    #[test]
+    fn test_from_tiktoken_rejects_bad_base64() {
+        let err = BpeTokenizer::from_tiktoken("not-valid-base64! 0\n", FxHashMap::default());
+        assert!(err.is_err());
+    }
+
     #[test]
     fn test_train_bpe() {
         let tok = train_bpe("the the the thing thing", 10);
@@ -350,4 +1366,78 @@ mod tests {
         // After training on repeated "the", it should merge into 1-2 tokens
         assert!(tokens.len() <= 3);
     }
+
+    #[test]
+    fn test_run_bpe_merge_loop_repeated_pair_matches_brute_force() {
+        // Rescans the whole corpus before every merge (no position-set
+        // bookkeeping to get wrong) so it's trustworthy as a reference.
+        fn brute_force_merges(
+            mut words: Vec<Vec<u32>>,
+            counts: &[i64],
+            mut next_id: u32,
+            stop_at_next_id: u32,
+            min_count: i64,
+        ) -> Vec<(u32, u32)> {
+            let mut merges = Vec::new();
+            while next_id < stop_at_next_id {
+                let mut pair_counts: FxHashMap<(u32, u32), i64> = FxHashMap::default();
+                for (wi, word) in words.iter().enumerate() {
+                    for p in word.windows(2) {
+                        *pair_counts.entry((p[0], p[1])).or_insert(0) += counts[wi];
+                    }
+                }
+                let best = pair_counts
+                    .iter()
+                    .max_by(|a, b| a.1.cmp(b.1).then_with(|| b.0.cmp(a.0)))
+                    .map(|(&k, &v)| (k, v));
+                let Some((pair, count)) = best else { break };
+                if count < min_count {
+                    break;
+                }
+                let new_id = next_id;
+                next_id += 1;
+                merges.push(pair);
+                for word in &mut words {
+                    let mut i = 0;
+                    while i + 1 < word.len() {
+                        if word[i] == pair.0 && word[i + 1] == pair.1 {
+                            word[i] = new_id;
+                            word.remove(i + 1);
+                        }
+                        i += 1;
+                    }
+                }
+            }
+            merges
+        }
+
+        // A single word with a repeated internal pair (`(2, 2)` occurs
+        // twice): losing one occurrence of a neighbor pair from
+        // `pair_positions` must not make the loop skip the word's other
+        // occurrence of that pair.
+        let word = vec![0u32, 2, 1, 0, 2, 2, 2];
+        let counts = vec![3i64];
+
+        let mut encoder: FxHashMap<Vec<u8>, u32> = FxHashMap::default();
+        let mut id_to_bytes: FxHashMap<u32, Vec<u8>> = FxHashMap::default();
+        for i in 0u32..3 {
+            encoder.insert(vec![i as u8], i);
+            id_to_bytes.insert(i, vec![i as u8]);
+        }
+        let mut words = vec![word.clone()];
+        let mut next_id = 3u32;
+        let merges = run_bpe_merge_loop(
+            &mut words,
+            &counts,
+            &mut encoder,
+            &mut id_to_bytes,
+            &mut next_id,
+            8,
+            1,
+        );
+        let actual: Vec<(u32, u32)> = merges.iter().map(|(l, r)| (encoder[l], encoder[r])).collect();
+
+        let reference = brute_force_merges(vec![word], &counts, 3, 8, 1);
+        assert_eq!(actual, reference);
+    }
 }
@@ -3,7 +3,9 @@
 //! Maps tokens to visual properties based on their content and ID.
 //! Categories drive both color and 3D node behavior.
 
+use rustc_hash::FxHashSet;
 use serde::{Deserialize, Serialize};
+use std::sync::{Mutex, OnceLock};
 
 /// High-level category for a token, drives visual treatment.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -27,31 +29,192 @@ pub enum TokenCategory {
     Fragment,
 }
 
+/// Unicode script grouping for a token's text, reported alongside
+/// `TokenCategory` rather than folded into it. BPE splits multibyte
+/// scripts (CJK, Cyrillic, Arabic, ...) into many small fragment tokens
+/// that would otherwise all render identically as plain `Word`/`Fragment`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenScript {
+    Latin,
+    Cyrillic,
+    Cjk,
+    Arabic,
+    Devanagari,
+    /// No alphabetic char found, or an alphabetic char outside the
+    /// ranges above (digits, punctuation, whitespace, other scripts).
+    Other,
+}
+
+/// Where a `VisualToken`'s `weight` came from, so the UI can indicate
+/// whether the "rarity glow" is measured data or a rough estimate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WeightSource {
+    /// Looked up from a loaded rarity table (real corpus frequency / merge rank).
+    Measured,
+    /// No rarity table covered this id; derived from `id / vocab_size`.
+    Estimated,
+}
+
 /// Maps tokens to colors and categories.
+#[derive(Clone)]
 pub struct TokenColorMap {
     /// Palette: category → [r, g, b]
     palette: [(TokenCategory, [u8; 3]); 8],
+    /// User-supplied common-word list, lowercased. `None` falls back to
+    /// the built-in English list (`is_common_word`).
+    common_words: Option<FxHashSet<String>>,
+    /// User-supplied code-keyword/operator list. `None` falls back to
+    /// the built-in list (`is_code_token`).
+    code_keywords: Option<FxHashSet<String>>,
+    /// Whether to apply the no-leading-space/short/high-id heuristic that
+    /// guesses sub-word fragments. Some domains (e.g. CJK-heavy vocabs)
+    /// want this off since it misfires on short multibyte words.
+    fragment_heuristic: bool,
 }
 
 impl Default for TokenColorMap {
     fn default() -> Self {
         Self {
-            palette: [
-                // Frost glass inspired palette
-                (TokenCategory::Whitespace,  [60, 70, 90]),       // dark slate
-                (TokenCategory::Punctuation, [120, 140, 170]),    // steel blue
-                (TokenCategory::CommonWord,  [0, 255, 204]),      // cyan-green (primary glow)
-                (TokenCategory::Word,        [125, 244, 255]),    // bright cyan
-                (TokenCategory::Numeric,     [255, 170, 50]),     // amber
-                (TokenCategory::Code,        [16, 185, 129]),     // emerald
-                (TokenCategory::Special,     [255, 80, 120]),     // hot pink
-                (TokenCategory::Fragment,    [160, 120, 255]),    // purple
-            ],
+            palette: DEFAULT_PALETTE,
+            common_words: None,
+            code_keywords: None,
+            fragment_heuristic: true,
         }
     }
 }
 
+/// Frost glass: the original palette, and the fallback for any category a
+/// custom config omits.
+const DEFAULT_PALETTE: [(TokenCategory, [u8; 3]); 8] = [
+    (TokenCategory::Whitespace,  [60, 70, 90]),       // dark slate
+    (TokenCategory::Punctuation, [120, 140, 170]),    // steel blue
+    (TokenCategory::CommonWord,  [0, 255, 204]),      // cyan-green (primary glow)
+    (TokenCategory::Word,        [125, 244, 255]),    // bright cyan
+    (TokenCategory::Numeric,     [255, 170, 50]),     // amber
+    (TokenCategory::Code,        [16, 185, 129]),     // emerald
+    (TokenCategory::Special,     [255, 80, 120]),     // hot pink
+    (TokenCategory::Fragment,    [160, 120, 255]),    // purple
+];
+
+/// Solarized-inspired dark theme.
+const SOLARIZED_DARK_PALETTE: [(TokenCategory, [u8; 3]); 8] = [
+    (TokenCategory::Whitespace,  [7, 54, 66]),        // base02
+    (TokenCategory::Punctuation, [88, 110, 117]),     // base01
+    (TokenCategory::CommonWord,  [133, 153, 0]),      // green
+    (TokenCategory::Word,        [38, 139, 210]),     // blue
+    (TokenCategory::Numeric,     [181, 137, 0]),      // yellow
+    (TokenCategory::Code,        [42, 161, 152]),     // cyan
+    (TokenCategory::Special,     [220, 50, 47]),       // red
+    (TokenCategory::Fragment,    [211, 54, 130]),      // magenta
+];
+
+/// High-contrast theme for accessibility / screen-share legibility.
+const HIGH_CONTRAST_PALETTE: [(TokenCategory, [u8; 3]); 8] = [
+    (TokenCategory::Whitespace,  [40, 40, 40]),
+    (TokenCategory::Punctuation, [200, 200, 200]),
+    (TokenCategory::CommonWord,  [0, 255, 0]),
+    (TokenCategory::Word,        [255, 255, 255]),
+    (TokenCategory::Numeric,     [255, 255, 0]),
+    (TokenCategory::Code,        [0, 255, 255]),
+    (TokenCategory::Special,     [255, 0, 0]),
+    (TokenCategory::Fragment,    [255, 0, 255]),
+];
+
+/// User-facing configuration for a [`TokenColorMap`], e.g. loaded from a
+/// JSON theme file. Any category missing from `palette` keeps its
+/// "frost glass" default color.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenColorMapConfig {
+    /// Sparse category → [r, g, b] overrides.
+    #[serde(default)]
+    pub palette: Vec<(TokenCategory, [u8; 3])>,
+    /// Replaces the built-in common-word list when present.
+    #[serde(default)]
+    pub common_words: Option<Vec<String>>,
+    /// Replaces the built-in code-keyword/operator list when present.
+    #[serde(default)]
+    pub code_keywords: Option<Vec<String>>,
+    /// See [`TokenColorMap`]'s `fragment_heuristic` field.
+    #[serde(default = "default_true")]
+    pub fragment_heuristic: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Default for TokenColorMapConfig {
+    fn default() -> Self {
+        Self {
+            palette: Vec::new(),
+            common_words: None,
+            code_keywords: None,
+            fragment_heuristic: true,
+        }
+    }
+}
+
+static ACTIVE_THEME: OnceLock<Mutex<TokenColorMap>> = OnceLock::new();
+
 impl TokenColorMap {
+    /// Build a `TokenColorMap` from a user-supplied config. Categories the
+    /// config's `palette` omits keep their "frost glass" default color.
+    pub fn from_config(config: TokenColorMapConfig) -> Self {
+        let mut palette = DEFAULT_PALETTE;
+        for (category, color) in config.palette {
+            if let Some(slot) = palette.iter_mut().find(|(cat, _)| *cat == category) {
+                slot.1 = color;
+            }
+        }
+
+        Self {
+            palette,
+            common_words: config
+                .common_words
+                .map(|words| words.into_iter().map(|w| w.to_lowercase()).collect()),
+            code_keywords: config.code_keywords.map(|kws| kws.into_iter().collect()),
+            fragment_heuristic: config.fragment_heuristic,
+        }
+    }
+
+    /// Look up one of the built-in named themes: `"frost_glass"`,
+    /// `"solarized_dark"`, or `"high_contrast"`. Returns `None` for an
+    /// unknown name so callers can fall back to `default()` or report an
+    /// error, as fits their context.
+    pub fn named(name: &str) -> Option<Self> {
+        let palette = match name {
+            "frost_glass" => DEFAULT_PALETTE,
+            "solarized_dark" => SOLARIZED_DARK_PALETTE,
+            "high_contrast" => HIGH_CONTRAST_PALETTE,
+            _ => return None,
+        };
+        Some(Self {
+            palette,
+            ..Default::default()
+        })
+    }
+
+    /// The process-wide active theme, used by `tokenize`/`IncrementalTokenizer`
+    /// to categorize and color tokens. Starts as `default()` and is
+    /// changed with `set_active` (e.g. via the WASM `setTheme` binding).
+    pub fn active() -> Self {
+        ACTIVE_THEME
+            .get_or_init(|| Mutex::new(Self::default()))
+            .lock()
+            .expect("active theme mutex poisoned")
+            .clone()
+    }
+
+    /// Replace the process-wide active theme.
+    pub fn set_active(map: Self) {
+        *ACTIVE_THEME
+            .get_or_init(|| Mutex::new(Self::default()))
+            .lock()
+            .expect("active theme mutex poisoned") = map;
+    }
+
     /// Determine the category of a token.
     pub fn categorize(&self, id: u32, text: &str) -> TokenCategory {
         // Special tokens (high IDs in most vocabs, or special text)
@@ -61,34 +224,54 @@ impl TokenColorMap {
 
         let trimmed = text.trim();
 
-        // Whitespace
+        // Whitespace (Unicode-aware: `trim` already strips any Unicode
+        // whitespace, not just ASCII spaces/tabs/newlines)
         if trimmed.is_empty() {
             return TokenCategory::Whitespace;
         }
 
-        // Numeric
-        if trimmed.chars().all(|c| c.is_ascii_digit() || c == '.' || c == ',') {
+        // Numeric: any Unicode numeric char (`char::is_numeric`), so any
+        // script's decimal digits (Arabic-Indic, fullwidth, Devanagari, ...),
+        // not just ASCII 0-9. This is not limited to decimal digits: other
+        // numeric chars such as `½` or `Ⅻ` count too. Require at least one
+        // numeric char so pure punctuation like "." or "..." isn't misclassified.
+        if trimmed.chars().any(|c| c.is_numeric())
+            && trimmed.chars().all(|c| c.is_numeric() || c == '.' || c == ',')
+        {
             return TokenCategory::Numeric;
         }
 
         // Code-like
-        if is_code_token(trimmed) {
+        let is_code = match &self.code_keywords {
+            Some(keywords) => keywords.contains(trimmed),
+            None => is_code_token(trimmed),
+        };
+        if is_code {
             return TokenCategory::Code;
         }
 
-        // Punctuation
-        if trimmed.chars().all(|c| c.is_ascii_punctuation()) {
+        // Punctuation/symbols: anything that isn't alphanumeric or
+        // whitespace, in any script (fullwidth punctuation, Unicode
+        // symbols, etc.), not just the ASCII punctuation set
+        if trimmed
+            .chars()
+            .all(|c| !c.is_alphanumeric() && !c.is_whitespace())
+        {
             return TokenCategory::Punctuation;
         }
 
         // Common words (leading space is typical in BPE)
         let word = text.trim().to_lowercase();
-        if is_common_word(&word) {
+        let is_common = match &self.common_words {
+            Some(words) => words.contains(&word),
+            None => is_common_word(&word),
+        };
+        if is_common {
             return TokenCategory::CommonWord;
         }
 
         // Fragments (sub-word pieces, no leading space, short, not standalone)
-        if !text.starts_with(' ') && text.len() <= 3 && id > 256 {
+        if self.fragment_heuristic && !text.starts_with(' ') && text.len() <= 3 && id > 256 {
             return TokenCategory::Fragment;
         }
 
@@ -104,10 +287,27 @@ impl TokenColorMap {
             .unwrap_or([125, 244, 255]) // fallback: bright cyan
     }
 
-    /// Compute a "rarity" weight 0.0-1.0 based on token ID.
-    /// Lower IDs (single bytes) = common = low weight.
-    /// Higher IDs (learned merges) = rarer = higher weight.
-    pub fn weight_for(&self, id: u32, vocab_size: usize) -> f32 {
+    /// Determine the dominant Unicode script of a token's text, based on
+    /// its first alphabetic char. Returns `TokenScript::Other` for tokens
+    /// with no alphabetic char (whitespace, numeric, punctuation) or in a
+    /// script this visualizer doesn't distinguish yet.
+    pub fn script_for(&self, text: &str) -> TokenScript {
+        text.chars()
+            .find(|c| c.is_alphabetic())
+            .map(classify_char_script)
+            .unwrap_or(TokenScript::Other)
+    }
+
+    /// Compute a "rarity" weight 0.0-1.0. Uses `measured_rarity` (from a
+    /// tokenizer's loaded rarity table, see `BpeTokenizer::rarity_for`)
+    /// when present, since BPE token IDs are only loosely correlated with
+    /// real frequency. Falls back to the `id / vocab_size` estimate
+    /// otherwise: lower IDs (single bytes) = common = low weight, higher
+    /// IDs (learned merges) = rarer = higher weight.
+    pub fn weight_for(&self, id: u32, vocab_size: usize, measured_rarity: Option<f32>) -> f32 {
+        if let Some(rarity) = measured_rarity {
+            return rarity;
+        }
         if vocab_size == 0 {
             return 0.5;
         }
@@ -115,6 +315,24 @@ impl TokenColorMap {
     }
 }
 
+/// Classify a single alphabetic char into the script buckets we
+/// distinguish. Ranges are Unicode block boundaries, not full script
+/// tables: good enough to separate the major multibyte scripts that BPE
+/// fragments heavily, not a substitute for `unicode-script` crate-level
+/// precision.
+fn classify_char_script(c: char) -> TokenScript {
+    match c as u32 {
+        0x0041..=0x024F => TokenScript::Latin,
+        0x0400..=0x04FF => TokenScript::Cyrillic,
+        0x0600..=0x06FF | 0x0750..=0x077F => TokenScript::Arabic,
+        0x0900..=0x097F => TokenScript::Devanagari,
+        0x3040..=0x30FF | 0x3400..=0x4DBF | 0x4E00..=0x9FFF | 0xF900..=0xFAFF => {
+            TokenScript::Cjk
+        }
+        _ => TokenScript::Other,
+    }
+}
+
 fn is_common_word(word: &str) -> bool {
     matches!(
         word,
@@ -165,6 +383,16 @@ mod tests {
         assert_eq!(cm.categorize(401, "3.14"), TokenCategory::Numeric);
     }
 
+    #[test]
+    fn test_categorize_numeric_requires_a_digit() {
+        let cm = TokenColorMap::default();
+        // Pure punctuation must not be swept up by the `.`/`,` separator
+        // allowance meant for numbers like "3.14" or "1,000".
+        assert_eq!(cm.categorize(402, "."), TokenCategory::Punctuation);
+        assert_eq!(cm.categorize(403, "..."), TokenCategory::Punctuation);
+        assert_eq!(cm.categorize(404, ","), TokenCategory::Punctuation);
+    }
+
     #[test]
     fn test_categorize_code() {
         let cm = TokenColorMap::default();
@@ -188,11 +416,96 @@ mod tests {
         assert_eq!(color, [0, 255, 204]); // cyan-green
     }
 
+    #[test]
+    fn test_categorize_unicode_numeric_and_punctuation() {
+        let cm = TokenColorMap::default();
+        // Arabic-Indic digits
+        assert_eq!(cm.categorize(600, "٤٢"), TokenCategory::Numeric);
+        // Fullwidth punctuation
+        assert_eq!(cm.categorize(601, "。"), TokenCategory::Punctuation);
+    }
+
+    #[test]
+    fn test_script_for() {
+        let cm = TokenColorMap::default();
+        assert_eq!(cm.script_for("hello"), TokenScript::Latin);
+        assert_eq!(cm.script_for("Привет"), TokenScript::Cyrillic);
+        assert_eq!(cm.script_for("你好"), TokenScript::Cjk);
+        assert_eq!(cm.script_for("مرحبا"), TokenScript::Arabic);
+        assert_eq!(cm.script_for("नमस्ते"), TokenScript::Devanagari);
+        assert_eq!(cm.script_for("42"), TokenScript::Other);
+        assert_eq!(cm.script_for(" "), TokenScript::Other);
+    }
+
     #[test]
     fn test_weight() {
         let cm = TokenColorMap::default();
-        let w_low = cm.weight_for(10, 100000);
-        let w_high = cm.weight_for(90000, 100000);
+        let w_low = cm.weight_for(10, 100000, None);
+        let w_high = cm.weight_for(90000, 100000, None);
         assert!(w_low < w_high);
     }
+
+    #[test]
+    fn test_weight_prefers_measured_rarity() {
+        let cm = TokenColorMap::default();
+        // A low ID would estimate as low weight, but a measured rarity
+        // should win regardless of what the estimate would say.
+        assert_eq!(cm.weight_for(10, 100000, Some(0.9)), 0.9);
+    }
+
+    #[test]
+    fn test_named_themes() {
+        assert!(TokenColorMap::named("solarized_dark").is_some());
+        assert!(TokenColorMap::named("high_contrast").is_some());
+        assert!(TokenColorMap::named("not_a_theme").is_none());
+    }
+
+    #[test]
+    fn test_from_config_palette_override() {
+        let config = TokenColorMapConfig {
+            palette: vec![(TokenCategory::Word, [1, 2, 3])],
+            ..Default::default()
+        };
+        let cm = TokenColorMap::from_config(config);
+        assert_eq!(cm.color_for(&TokenCategory::Word), [1, 2, 3]);
+        // Untouched categories keep the default color
+        assert_eq!(cm.color_for(&TokenCategory::Numeric), [255, 170, 50]);
+    }
+
+    #[test]
+    fn test_from_config_custom_word_lists() {
+        let config = TokenColorMapConfig {
+            common_words: Some(vec!["bonjour".to_string()]),
+            code_keywords: Some(vec!["fn".to_string()]),
+            ..Default::default()
+        };
+        let cm = TokenColorMap::from_config(config);
+        assert_eq!(cm.categorize(300, "bonjour"), TokenCategory::CommonWord);
+        // No longer in the custom list, so it falls through to Word. Leading
+        // space (typical for a BPE word start) keeps the fragment heuristic
+        // from claiming it first.
+        assert_eq!(cm.categorize(301, " the"), TokenCategory::Word);
+        assert_eq!(cm.categorize(302, "fn"), TokenCategory::Code);
+    }
+
+    #[test]
+    fn test_from_config_fragment_heuristic_toggle() {
+        let config = TokenColorMapConfig {
+            fragment_heuristic: false,
+            ..Default::default()
+        };
+        let cm = TokenColorMap::from_config(config);
+        assert_eq!(cm.categorize(300, "xyz"), TokenCategory::Word);
+    }
+
+    #[test]
+    fn test_active_theme_round_trip() {
+        TokenColorMap::set_active(TokenColorMap::named("high_contrast").unwrap());
+        assert_eq!(
+            TokenColorMap::active().color_for(&TokenCategory::Word),
+            [255, 255, 255]
+        );
+        // Restore the default so other tests aren't affected by ordering.
+        TokenColorMap::set_active(TokenColorMap::default());
+    }
 }
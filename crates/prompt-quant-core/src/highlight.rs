@@ -0,0 +1,193 @@
+//! Substring/regex highlight search over already-tokenized text.
+//!
+//! A single match frequently straddles several BPE tokens, so each match
+//! reports both its `(char_start, char_end)` range in the source text and
+//! the contiguous run of `VisualToken` indices it overlaps (including
+//! partial overlaps at either edge), so the UI can tint just the covered
+//! portion of boundary tokens.
+
+use crate::{TokenizeResult, VisualToken};
+use regex::Regex;
+
+/// One match of a highlight query against the source text.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct HighlightMatch {
+    pub char_start: usize,
+    pub char_end: usize,
+    pub byte_start: usize,
+    pub byte_end: usize,
+    /// First token index this match overlaps.
+    pub token_start: usize,
+    /// Last token index this match overlaps (inclusive).
+    pub token_end: usize,
+}
+
+/// One run of a search-results-style alternating highlight rendering.
+/// Walking `segments` in order and concatenating `text` reproduces the
+/// original input.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct HighlightSegment {
+    pub text: String,
+    pub highlighted: bool,
+}
+
+/// Result of `highlight_matches`.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct HighlightResult {
+    pub matches: Vec<HighlightMatch>,
+    pub segments: Vec<HighlightSegment>,
+}
+
+/// Compile `query` as a regex. Falls back to a literal (escaped) pattern
+/// only if it isn't valid regex syntax, so a bare substring like `foo(bar`
+/// still searches literally; a valid pattern such as `a.b` is used as a
+/// regex (its `.` matches any char).
+fn compile_query(query: &str) -> Option<Regex> {
+    if query.is_empty() {
+        return None;
+    }
+    Regex::new(query)
+        .or_else(|_| Regex::new(&regex::escape(query)))
+        .ok()
+}
+
+/// Find every match of `query` in `text`, the `tokens` each one overlaps,
+/// and an alternating highlighted/unhighlighted segmentation of `text`.
+pub fn highlight_matches(text: &str, tokens: &[VisualToken], query: &str) -> HighlightResult {
+    let Some(re) = compile_query(query) else {
+        return HighlightResult {
+            matches: Vec::new(),
+            segments: no_match_segments(text),
+        };
+    };
+
+    let mut matches = Vec::new();
+    for m in re.find_iter(text) {
+        let byte_start = m.start();
+        let byte_end = m.end();
+        if byte_start == byte_end {
+            continue;
+        }
+
+        let overlapping = tokens
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| t.byte_start < byte_end && t.byte_end > byte_start)
+            .map(|(i, _)| i);
+        let (token_start, token_end) = match (overlapping.clone().next(), overlapping.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => continue,
+        };
+
+        matches.push(HighlightMatch {
+            char_start: text[..byte_start].chars().count(),
+            char_end: text[..byte_end].chars().count(),
+            byte_start,
+            byte_end,
+            token_start,
+            token_end,
+        });
+    }
+
+    let segments = build_segments(text, &matches);
+    HighlightResult { matches, segments }
+}
+
+/// Convenience: highlight within the text a `TokenizeResult` came from.
+pub fn highlight_in_result(text: &str, result: &TokenizeResult, query: &str) -> HighlightResult {
+    highlight_matches(text, &result.tokens, query)
+}
+
+fn no_match_segments(text: &str) -> Vec<HighlightSegment> {
+    if text.is_empty() {
+        Vec::new()
+    } else {
+        vec![HighlightSegment {
+            text: text.to_string(),
+            highlighted: false,
+        }]
+    }
+}
+
+fn build_segments(text: &str, matches: &[HighlightMatch]) -> Vec<HighlightSegment> {
+    let mut segments = Vec::new();
+    let mut cursor = 0usize;
+    for m in matches {
+        if m.byte_start > cursor {
+            segments.push(HighlightSegment {
+                text: text[cursor..m.byte_start].to_string(),
+                highlighted: false,
+            });
+        }
+        segments.push(HighlightSegment {
+            text: text[m.byte_start..m.byte_end].to_string(),
+            highlighted: true,
+        });
+        cursor = m.byte_end;
+    }
+    if cursor < text.len() {
+        segments.push(HighlightSegment {
+            text: text[cursor..].to_string(),
+            highlighted: false,
+        });
+    }
+    segments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vocab::VocabRegistry;
+    use crate::VocabId;
+
+    fn tokenize(text: &str) -> TokenizeResult {
+        crate::tokenize(text, &VocabId::cl100k())
+    }
+
+    #[test]
+    fn finds_simple_substring() {
+        let result = tokenize("hello world");
+        let h = highlight_in_result("hello world", &result, "world");
+        assert_eq!(h.matches.len(), 1);
+        assert_eq!(h.matches[0].char_start, 6);
+        assert_eq!(h.matches[0].char_end, 11);
+    }
+
+    #[test]
+    fn match_spans_multiple_tokens() {
+        let reg = VocabRegistry::global();
+        let tok = reg.get(&VocabId::cl100k());
+        let raw = tok.encode("internationalization");
+        assert!(raw.len() > 1, "expected the word to split into several tokens");
+        let result = crate::tokenize("internationalization", &VocabId::cl100k());
+        let h = highlight_in_result("internationalization", &result, "national");
+        assert_eq!(h.matches.len(), 1);
+        let m = &h.matches[0];
+        assert!(m.token_end > m.token_start);
+    }
+
+    #[test]
+    fn segments_reconstruct_original_text() {
+        let result = tokenize("hello world hello");
+        let h = highlight_in_result("hello world hello", &result, "hello");
+        let joined: String = h.segments.iter().map(|s| s.text.as_str()).collect();
+        assert_eq!(joined, "hello world hello");
+        assert_eq!(h.matches.len(), 2);
+    }
+
+    #[test]
+    fn no_match_returns_single_unhighlighted_segment() {
+        let result = tokenize("hello world");
+        let h = highlight_in_result("hello world", &result, "xyz");
+        assert!(h.matches.is_empty());
+        assert_eq!(h.segments.len(), 1);
+        assert!(!h.segments[0].highlighted);
+    }
+
+    #[test]
+    fn regex_query_matches() {
+        let result = tokenize("foo123bar456");
+        let h = highlight_in_result("foo123bar456", &result, r"\d+");
+        assert_eq!(h.matches.len(), 2);
+    }
+}
@@ -5,12 +5,17 @@
 //! This keeps latency under 1ms even for long inputs.
 
 use crate::bpe::BpeTokenizer;
-use crate::color::TokenColorMap;
+use crate::color::{TokenColorMap, WeightSource};
 use crate::{TokenizeResult, VisualToken};
+use std::sync::Arc;
 
 /// Maintains state between tokenization calls for efficient updates.
-pub struct IncrementalTokenizer<'a> {
-    tokenizer: &'a BpeTokenizer,
+///
+/// Holds an owned `Arc<BpeTokenizer>` rather than borrowing one, so it can
+/// be embedded in a long-lived host object (e.g. the WASM binding) without
+/// fighting a borrow's lifetime across calls.
+pub struct IncrementalTokenizer {
+    tokenizer: Arc<BpeTokenizer>,
     color_map: TokenColorMap,
     /// Last known input text
     last_input: String,
@@ -20,17 +25,29 @@ pub struct IncrementalTokenizer<'a> {
     vocab_id: String,
 }
 
-impl<'a> IncrementalTokenizer<'a> {
-    pub fn new(tokenizer: &'a BpeTokenizer, vocab_id: &str) -> Self {
+impl IncrementalTokenizer {
+    pub fn new(tokenizer: Arc<BpeTokenizer>, vocab_id: &str) -> Self {
         Self {
             tokenizer,
-            color_map: TokenColorMap::default(),
+            color_map: TokenColorMap::active(),
             last_input: String::new(),
             last_tokens: Vec::new(),
             vocab_id: vocab_id.to_string(),
         }
     }
 
+    /// The vocab ID this tokenizer is tracking state for.
+    pub fn vocab_id(&self) -> &str {
+        &self.vocab_id
+    }
+
+    /// Re-sync to the current process-wide active theme (see
+    /// `TokenColorMap::active`). Cached tokens keep their old colors until
+    /// the next `update`/`reset` recomputes them.
+    pub fn sync_theme(&mut self) {
+        self.color_map = TokenColorMap::active();
+    }
+
     /// Tokenize the input, reusing cached results where possible.
     /// Returns the full token list plus a `changed_range` indicating
     /// which token indices were affected.
@@ -41,6 +58,8 @@ impl<'a> IncrementalTokenizer<'a> {
                     tokens: self.last_tokens.clone(),
                     total_tokens: self.last_tokens.len(),
                     vocab_id: self.vocab_id.clone(),
+                    remaining: None,
+                    over_budget: false,
                 },
                 changed_range: None,
             };
@@ -78,18 +97,30 @@ impl<'a> IncrementalTokenizer<'a> {
                 .position(|t| t.byte_end > changed_start)
                 .unwrap_or(0);
 
-            // Re-tokenize from first_affected token's start to end of changed region + margin
+            // Re-tokenize from first_affected token's start through the end
+            // of the input (not just to the end of the changed region + a
+            // fixed margin): `retok_start` is an old token boundary that
+            // falls inside the common prefix. Byte-level BPE tokens can split
+            // a multibyte char, so first step back to a token that starts on
+            // a char boundary in `input`. Re-encoding everything after
+            // it in one pass — rather than splitting into a windowed
+            // "middle" slice plus an independently re-encoded "tail" — means
+            // there's no second cut point that could land mid-codepoint or
+            // disagree with how a full `encode` would have merged across
+            // that seam.
+            let mut first_affected = first_affected;
+            while first_affected > 0
+                && !input.is_char_boundary(self.last_tokens[first_affected].byte_start)
+            {
+                first_affected -= 1;
+            }
             let retok_start = if first_affected > 0 {
                 self.last_tokens[first_affected].byte_start
             } else {
                 0
             };
 
-            // Include some trailing context for proper BPE merging
-            let retok_end = (changed_end + 32).min(input.len());
-
-            // Get the slice to re-tokenize
-            let slice = &input[retok_start..retok_end];
+            let slice = &input[retok_start..];
             let raw_tokens = self.tokenizer.encode(slice);
 
             // Build visual tokens for the re-tokenized region
@@ -100,7 +131,14 @@ impl<'a> IncrementalTokenizer<'a> {
                 let char_len = rt.text.chars().count();
                 let category = self.color_map.categorize(rt.id, &rt.text);
                 let color = self.color_map.color_for(&category);
-                let weight = self.color_map.weight_for(rt.id, self.tokenizer.vocab_size());
+                let script = self.color_map.script_for(&rt.text);
+                let rarity = self.tokenizer.rarity_for(rt.id);
+                let weight = self.color_map.weight_for(rt.id, self.tokenizer.vocab_size(), rarity);
+                let weight_source = if rarity.is_some() {
+                    WeightSource::Measured
+                } else {
+                    WeightSource::Estimated
+                };
 
                 new_mid.push(VisualToken {
                     id: rt.id,
@@ -111,48 +149,20 @@ impl<'a> IncrementalTokenizer<'a> {
                     char_end: char_offset + char_len,
                     color,
                     category,
+                    script,
                     weight,
+                    weight_source,
+                    over_budget: false,
                 });
                 char_offset += char_len;
             }
 
-            // Re-tokenize the tail if needed
-            let tail_tokens = if retok_end < input.len() {
-                let tail = &input[retok_end..];
-                let raw_tail = self.tokenizer.encode(tail);
-                let mut tail_vis = Vec::with_capacity(raw_tail.len());
-                let mut tail_char_offset = input[..retok_end].chars().count();
-                for rt in &raw_tail {
-                    let char_len = rt.text.chars().count();
-                    let category = self.color_map.categorize(rt.id, &rt.text);
-                    let color = self.color_map.color_for(&category);
-                    let weight =
-                        self.color_map.weight_for(rt.id, self.tokenizer.vocab_size());
-                    tail_vis.push(VisualToken {
-                        id: rt.id,
-                        text: rt.text.clone(),
-                        byte_start: retok_end + rt.byte_start,
-                        byte_end: retok_end + rt.byte_end,
-                        char_start: tail_char_offset,
-                        char_end: tail_char_offset + char_len,
-                        color,
-                        category,
-                        weight,
-                    });
-                    tail_char_offset += char_len;
-                }
-                tail_vis
-            } else {
-                Vec::new()
-            };
-
-            // Assemble: prefix tokens + re-tokenized middle + tail
+            // Assemble: prefix tokens + re-tokenized remainder
             let prefix_tokens: Vec<VisualToken> = self.last_tokens[..first_affected].to_vec();
 
             let changed_start_idx = prefix_tokens.len();
             let mut all_tokens = prefix_tokens;
             all_tokens.extend(new_mid);
-            all_tokens.extend(tail_tokens);
             let changed_end_idx = all_tokens.len();
 
             let total = all_tokens.len();
@@ -164,6 +174,8 @@ impl<'a> IncrementalTokenizer<'a> {
                     tokens: all_tokens,
                     total_tokens: total,
                     vocab_id: self.vocab_id.clone(),
+                    remaining: None,
+                    over_budget: false,
                 },
                 changed_range: Some((changed_start_idx, changed_end_idx)),
             }
@@ -195,7 +207,14 @@ impl<'a> IncrementalTokenizer<'a> {
             let char_len = rt.text.chars().count();
             let category = self.color_map.categorize(rt.id, &rt.text);
             let color = self.color_map.color_for(&category);
-            let weight = self.color_map.weight_for(rt.id, self.tokenizer.vocab_size());
+            let script = self.color_map.script_for(&rt.text);
+            let rarity = self.tokenizer.rarity_for(rt.id);
+            let weight = self.color_map.weight_for(rt.id, self.tokenizer.vocab_size(), rarity);
+            let weight_source = if rarity.is_some() {
+                WeightSource::Measured
+            } else {
+                WeightSource::Estimated
+            };
 
             tokens.push(VisualToken {
                 id: rt.id,
@@ -206,7 +225,10 @@ impl<'a> IncrementalTokenizer<'a> {
                 char_end: char_offset + char_len,
                 color,
                 category,
+                script,
                 weight,
+                weight_source,
+                over_budget: false,
             });
             char_offset += char_len;
         }
@@ -216,11 +238,14 @@ impl<'a> IncrementalTokenizer<'a> {
             tokens,
             total_tokens: total,
             vocab_id: self.vocab_id.clone(),
+            remaining: None,
+            over_budget: false,
         }
     }
 }
 
 /// Result from incremental tokenization.
+#[derive(Debug, Clone, serde::Serialize)]
 pub struct IncrementalResult {
     /// Full token list
     pub result: TokenizeResult,
@@ -259,8 +284,8 @@ mod tests {
     use crate::vocab::VocabRegistry;
     use crate::VocabId;
 
-    fn make_incremental<'a>(tok: &'a BpeTokenizer) -> IncrementalTokenizer<'a> {
-        IncrementalTokenizer::new(tok, "test")
+    fn make_incremental(tok: &BpeTokenizer) -> IncrementalTokenizer {
+        IncrementalTokenizer::new(Arc::new(tok.clone()), "test")
     }
 
     #[test]
@@ -304,6 +329,28 @@ mod tests {
         assert!(s == 0 || s <= 1);
     }
 
+    #[test]
+    fn test_incremental_multibyte_edit_matches_full_tokenize() {
+        // A change near the start of a string with multibyte chars after it
+        // used to land the re-tokenize window mid-codepoint and panic; it
+        // should now match a full tokenize exactly.
+        let reg = VocabRegistry::global();
+        let tok = reg.get(&VocabId::cl100k());
+        let mut inc = make_incremental(tok);
+
+        let text: String = std::iter::once('a').chain(std::iter::repeat_n('你', 12)).collect();
+        inc.update(&text);
+
+        let edited = format!("b{}", &text[1..]);
+        let r = inc.update(&edited);
+
+        let expected = inc.full_tokenize(&edited);
+        assert_eq!(
+            r.result.tokens.iter().map(|t| t.id).collect::<Vec<_>>(),
+            expected.tokens.iter().map(|t| t.id).collect::<Vec<_>>()
+        );
+    }
+
     #[test]
     fn test_reset() {
         let reg = VocabRegistry::global();
@@ -6,15 +6,20 @@
 
 pub mod bpe;
 pub mod color;
+pub mod highlight;
 pub mod incremental;
+pub mod search;
 pub mod vocab;
 
 pub use bpe::BpeTokenizer;
-pub use color::{TokenCategory, TokenColorMap};
+pub use color::{TokenCategory, TokenColorMap, TokenColorMapConfig, TokenScript, WeightSource};
+pub use highlight::{highlight_in_result, highlight_matches, HighlightMatch, HighlightResult, HighlightSegment};
 pub use incremental::IncrementalTokenizer;
+pub use search::{fuzzy_match, CharBag, FuzzyMatch, TokenSearchResult};
 pub use vocab::{VocabId, VocabRegistry};
 
 use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
 
 /// A single token with all metadata needed for visualization.
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -35,8 +40,19 @@ pub struct VisualToken {
     pub color: [u8; 3],
     /// Category classification for grouping
     pub category: TokenCategory,
+    /// Dominant Unicode script of the token's text (Latin, CJK, ...),
+    /// reported alongside `category` so the UI can color multibyte
+    /// scripts distinctly instead of lumping them all into `Word`.
+    pub script: TokenScript,
     /// Normalized "weight" 0.0-1.0 (frequency-based rarity)
     pub weight: f32,
+    /// Whether `weight` came from a loaded rarity table or the
+    /// `id / vocab_size` estimate.
+    pub weight_source: WeightSource,
+    /// `true` once the running token count has crossed the context-window
+    /// budget (see `tokenize_with_budget`), so the UI can render this and
+    /// every later token in a warning color.
+    pub over_budget: bool,
 }
 
 /// Result of tokenizing a complete input.
@@ -45,15 +61,66 @@ pub struct TokenizeResult {
     pub tokens: Vec<VisualToken>,
     pub total_tokens: usize,
     pub vocab_id: String,
+    /// Tokens left before `budget` is reached, or negative once exceeded.
+    /// `None` when no budget was supplied or known for this vocab.
+    pub remaining: Option<isize>,
+    /// `true` if `total_tokens` exceeds the budget.
+    pub over_budget: bool,
 }
 
-/// Top-level convenience: tokenize text with a given vocab.
+/// Top-level convenience: tokenize text with a given vocab. No budget guard.
 pub fn tokenize(text: &str, vocab: &VocabId) -> TokenizeResult {
     let registry = VocabRegistry::global();
     let tokenizer = registry.get(vocab);
-    let color_map = TokenColorMap::default();
+    let raw_tokens = tokenizer.encode(text);
+    build_result(raw_tokens, tokenizer, vocab, None)
+}
+
+/// Like `tokenize`, but samples BPE dropout (if the vocab's tokenizer was
+/// built with `BpeTokenizer::with_dropout`) from a RNG seeded with `seed`,
+/// so the same seed reproduces the same alternate segmentation.
+pub fn tokenize_with_seed(text: &str, vocab: &VocabId, seed: u64) -> TokenizeResult {
+    let registry = VocabRegistry::global();
+    let tokenizer = registry.get(vocab);
+    let raw_tokens = tokenizer.encode_with_seed(text, seed);
+    build_result(raw_tokens, tokenizer, vocab, None)
+}
 
+/// Tokenize text with an optional context-window `budget`. If `budget` is
+/// `None`, falls back to `vocab::known_context_limit` for this vocab (if
+/// any) so callers get a live "tokens remaining" indicator without having
+/// to supply the number manually.
+pub fn tokenize_with_budget(text: &str, vocab: &VocabId, budget: Option<usize>) -> TokenizeResult {
+    let registry = VocabRegistry::global();
+    let tokenizer = registry.get(vocab);
     let raw_tokens = tokenizer.encode(text);
+    let budget = budget.or_else(|| vocab::known_context_limit(vocab));
+    build_result(raw_tokens, tokenizer, vocab, budget)
+}
+
+static ESTIMATE_PATTERN: OnceLock<regex::Regex> = OnceLock::new();
+
+/// Cheap, vocabulary-free ballpark token count for when no real
+/// `BpeTokenizer` is loaded yet (e.g. a vocab file is still downloading).
+/// Counts Unicode word runs, digit runs, punctuation runs, and whitespace
+/// runs — roughly the same boundaries the GPT pre-tokenization pattern
+/// splits on — without running any BPE merges, so callers get an instant
+/// ballpark for a token-budget guard instead of no number at all.
+pub fn estimate_token_length(text: &str) -> usize {
+    let pattern = ESTIMATE_PATTERN.get_or_init(|| {
+        regex::Regex::new(r"\p{L}+|\p{N}+|[^\s\p{L}\p{N}]+|\s+")
+            .expect("static token-estimate pattern is valid")
+    });
+    pattern.find_iter(text).count()
+}
+
+fn build_result(
+    raw_tokens: Vec<bpe::RawToken>,
+    tokenizer: &BpeTokenizer,
+    vocab: &VocabId,
+    budget: Option<usize>,
+) -> TokenizeResult {
+    let color_map = TokenColorMap::active();
     let mut tokens = Vec::with_capacity(raw_tokens.len());
 
     let mut char_offset = 0;
@@ -61,7 +128,14 @@ pub fn tokenize(text: &str, vocab: &VocabId) -> TokenizeResult {
         let char_len = rt.text.chars().count();
         let category = color_map.categorize(rt.id, &rt.text);
         let color = color_map.color_for(&category);
-        let weight = color_map.weight_for(rt.id, tokenizer.vocab_size());
+        let script = color_map.script_for(&rt.text);
+        let rarity = tokenizer.rarity_for(rt.id);
+        let weight = color_map.weight_for(rt.id, tokenizer.vocab_size(), rarity);
+        let weight_source = if rarity.is_some() {
+            WeightSource::Measured
+        } else {
+            WeightSource::Estimated
+        };
 
         tokens.push(VisualToken {
             id: rt.id,
@@ -72,15 +146,81 @@ pub fn tokenize(text: &str, vocab: &VocabId) -> TokenizeResult {
             char_end: char_offset + char_len,
             color,
             category,
+            script,
             weight,
+            weight_source,
+            over_budget: false,
         });
         char_offset += char_len;
     }
 
     let total_tokens = tokens.len();
+
+    let (remaining, over_budget) = match budget {
+        Some(limit) => {
+            let remaining = limit as isize - total_tokens as isize;
+            let over_budget = remaining < 0;
+            if over_budget {
+                for token in tokens.iter_mut().skip(limit) {
+                    token.over_budget = true;
+                }
+            }
+            (Some(remaining), over_budget)
+        }
+        None => (None, false),
+    };
+
     TokenizeResult {
         tokens,
         total_tokens,
         vocab_id: vocab.as_str().to_string(),
+        remaining,
+        over_budget,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize_no_budget() {
+        let result = tokenize("hello world", &VocabId::cl100k());
+        assert_eq!(result.remaining, None);
+        assert!(!result.over_budget);
+        assert!(result.tokens.iter().all(|t| !t.over_budget));
+    }
+
+    #[test]
+    fn test_tokenize_with_budget_under_limit() {
+        let result = tokenize_with_budget("hi", &VocabId::cl100k(), Some(1000));
+        assert_eq!(result.remaining, Some(1000 - result.total_tokens as isize));
+        assert!(!result.over_budget);
+    }
+
+    #[test]
+    fn test_tokenize_with_budget_over_limit() {
+        let result = tokenize_with_budget("hello there, this is a longer sentence", &VocabId::cl100k(), Some(2));
+        assert!(result.over_budget);
+        assert!(result.remaining.unwrap() < 0);
+        assert!(result.tokens[2].over_budget);
+        assert!(!result.tokens[0].over_budget);
+    }
+
+    #[test]
+    fn test_tokenize_falls_back_to_known_context_limit() {
+        let result = tokenize_with_budget("hi", &VocabId::p50k(), None);
+        assert_eq!(result.remaining, Some(4_097 - result.total_tokens as isize));
+    }
+
+    #[test]
+    fn test_estimate_token_length_basic() {
+        // "hello" + " " + "world" + "!" = 4 runs
+        assert_eq!(estimate_token_length("hello world!"), 4);
+    }
+
+    #[test]
+    fn test_estimate_token_length_empty() {
+        assert_eq!(estimate_token_length(""), 0);
     }
 }
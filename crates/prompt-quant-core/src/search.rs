@@ -0,0 +1,256 @@
+//! Fuzzy vocabulary search.
+//!
+//! Lets a caller type part of a token's decoded text (e.g. `endoft`,
+//! `println`) and find matching vocabulary entries ranked by relevance,
+//! for a "jump to token" style lookup in the UI.
+
+use crate::vocab::{VocabId, VocabRegistry};
+
+/// 64-bit bitmask of the (lowercased) characters a string contains.
+///
+/// Used to cheaply reject candidates that can't possibly match a fuzzy
+/// query before running the more expensive DP scorer: if the query's bag
+/// isn't a subset of the candidate's bag, no subsequence match exists.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CharBag(u64);
+
+impl CharBag {
+    /// Build the bag for a string.
+    pub fn of(s: &str) -> Self {
+        let mut bits = 0u64;
+        for c in s.chars() {
+            bits |= 1u64 << Self::bucket(c);
+        }
+        Self(bits)
+    }
+
+    /// Map a char onto one of 37 buckets: a-z, 0-9, and a catch-all for
+    /// everything else (punctuation, whitespace, non-ASCII).
+    fn bucket(c: char) -> u32 {
+        let lower = c.to_ascii_lowercase();
+        if lower.is_ascii_lowercase() {
+            (lower as u8 - b'a') as u32
+        } else if lower.is_ascii_digit() {
+            26 + (lower as u8 - b'0') as u32
+        } else {
+            36
+        }
+    }
+
+    /// `true` if every bucket set in `query` is also set in `self`.
+    pub fn contains_all(&self, query: &CharBag) -> bool {
+        (self.0 & query.0) == query.0
+    }
+}
+
+/// The result of scoring a single candidate string against a query.
+#[derive(Debug, Clone)]
+pub struct FuzzyMatch {
+    /// Higher is a better match.
+    pub score: i64,
+    /// Char indices into the candidate that matched, in ascending order.
+    pub positions: Vec<usize>,
+}
+
+/// Fuzzy-subsequence match `query` against `candidate`.
+///
+/// Runs a DP over query chars x candidate chars that only advances on
+/// character matches (case-insensitive), rewarding consecutive matches,
+/// matches right after a separator or case change, and matches at the
+/// start of the string. Returns `None` if `query` isn't a subsequence of
+/// `candidate` at all.
+pub fn fuzzy_match(query: &str, candidate: &str) -> Option<FuzzyMatch> {
+    if query.is_empty() {
+        return Some(FuzzyMatch {
+            score: 0,
+            positions: Vec::new(),
+        });
+    }
+
+    let query_bag = CharBag::of(query);
+    let candidate_bag = CharBag::of(candidate);
+    if !candidate_bag.contains_all(&query_bag) {
+        return None;
+    }
+
+    let q: Vec<char> = query.chars().collect();
+    let c: Vec<char> = candidate.chars().collect();
+    let q_lower: Vec<char> = q.iter().map(|ch| ch.to_ascii_lowercase()).collect();
+    let c_lower: Vec<char> = c.iter().map(|ch| ch.to_ascii_lowercase()).collect();
+
+    let n = q.len();
+    let m = c.len();
+    if n > m {
+        return None;
+    }
+
+    const NEG_INF: i64 = i64::MIN / 2;
+    const START_BONUS: i64 = 8;
+    const BOUNDARY_BONUS: i64 = 6;
+    const CONSECUTIVE_BONUS: i64 = 10;
+    const MATCH_SCORE: i64 = 1;
+
+    // dp[i][j]: best score matching q[..i] using c[..j].
+    // via_match[i][j]: whether the best path to this cell matched c[j-1] to q[i-1].
+    let mut dp = vec![vec![NEG_INF; m + 1]; n + 1];
+    let mut via_match = vec![vec![false; m + 1]; n + 1];
+    for j in 0..=m {
+        dp[0][j] = 0;
+    }
+
+    for i in 1..=n {
+        for j in 1..=m {
+            let skip = dp[i][j - 1];
+
+            let mut matched = NEG_INF;
+            if q_lower[i - 1] == c_lower[j - 1] && dp[i - 1][j - 1] > NEG_INF {
+                let mut bonus = MATCH_SCORE;
+                if j == 1 {
+                    bonus += START_BONUS;
+                } else {
+                    let prev = c[j - 2];
+                    let is_separator = prev == ' ' || prev == '_' || prev == '-' || prev == '.';
+                    let is_case_boundary = prev.is_lowercase() && c[j - 1].is_uppercase();
+                    if is_separator || is_case_boundary {
+                        bonus += BOUNDARY_BONUS;
+                    }
+                }
+                if via_match[i - 1][j - 1] {
+                    bonus += CONSECUTIVE_BONUS;
+                }
+                matched = dp[i - 1][j - 1] + bonus;
+            }
+
+            if matched >= skip && matched > NEG_INF {
+                dp[i][j] = matched;
+                via_match[i][j] = true;
+            } else {
+                dp[i][j] = skip;
+                via_match[i][j] = false;
+            }
+        }
+    }
+
+    if dp[n][m] <= NEG_INF {
+        return None;
+    }
+
+    let mut positions = Vec::with_capacity(n);
+    let mut i = n;
+    let mut j = m;
+    while i > 0 && j > 0 {
+        if via_match[i][j] {
+            positions.push(j - 1);
+            i -= 1;
+            j -= 1;
+        } else {
+            j -= 1;
+        }
+    }
+    positions.reverse();
+
+    Some(FuzzyMatch {
+        score: dp[n][m],
+        positions,
+    })
+}
+
+/// One vocabulary entry found by `VocabRegistry::fuzzy_search`.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct TokenSearchResult {
+    pub vocab_id: VocabId,
+    pub token_id: u32,
+    pub token_text: String,
+    pub score: i64,
+    /// Char indices into `token_text` that matched the query, for the UI
+    /// to bold.
+    pub matched_indices: Vec<usize>,
+}
+
+impl VocabRegistry {
+    /// Fuzzy-search every registered vocabulary's token text for `query`,
+    /// returning up to `limit` results sorted by descending score.
+    pub fn fuzzy_search(&self, query: &str, limit: usize) -> Vec<TokenSearchResult> {
+        let mut results: Vec<TokenSearchResult> = Vec::new();
+
+        for (vocab_id, tokenizer) in self.entries() {
+            for (token_id, token_text) in tokenizer.tokens() {
+                if let Some(m) = fuzzy_match(query, &token_text) {
+                    results.push(TokenSearchResult {
+                        vocab_id: vocab_id.clone(),
+                        token_id,
+                        token_text,
+                        score: m.score,
+                        matched_indices: m.positions,
+                    });
+                }
+            }
+        }
+
+        results.sort_by(|a, b| b.score.cmp(&a.score));
+        results.truncate(limit);
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn char_bag_rejects_missing_letters() {
+        let query = CharBag::of("xyz");
+        let candidate = CharBag::of("hello world");
+        assert!(!candidate.contains_all(&query));
+    }
+
+    #[test]
+    fn char_bag_accepts_subsequence_letters() {
+        let query = CharBag::of("helo");
+        let candidate = CharBag::of("hello world");
+        assert!(candidate.contains_all(&query));
+    }
+
+    #[test]
+    fn fuzzy_match_rejects_non_subsequence() {
+        assert!(fuzzy_match("xyz", "hello").is_none());
+    }
+
+    #[test]
+    fn fuzzy_match_finds_subsequence() {
+        let m = fuzzy_match("hlo", "hello").expect("should match");
+        // The second "l" (index 3) wins: it earns the consecutive bonus with "o".
+        assert_eq!(m.positions, vec![0, 3, 4]);
+    }
+
+    #[test]
+    fn fuzzy_match_prefers_consecutive_and_prefix() {
+        let consecutive = fuzzy_match("hel", "hello").unwrap();
+        let scattered = fuzzy_match("hel", "h-e-l-lo").unwrap();
+        assert!(consecutive.score > scattered.score);
+    }
+
+    #[test]
+    fn fuzzy_match_rewards_word_boundary() {
+        let boundary = fuzzy_match("fb", "foo_bar").unwrap();
+        let no_boundary = fuzzy_match("ob", "foobar").unwrap();
+        assert!(boundary.score > no_boundary.score);
+    }
+
+    #[test]
+    fn fuzzy_match_empty_query_matches_everything() {
+        let m = fuzzy_match("", "anything").unwrap();
+        assert_eq!(m.score, 0);
+        assert!(m.positions.is_empty());
+    }
+
+    #[test]
+    fn registry_fuzzy_search_ranks_results() {
+        let reg = VocabRegistry::global();
+        let results = reg.fuzzy_search("hello", 5);
+        assert!(results.len() <= 5);
+        for pair in results.windows(2) {
+            assert!(pair[0].score >= pair[1].score);
+        }
+    }
+}
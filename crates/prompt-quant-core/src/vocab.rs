@@ -6,6 +6,7 @@
 use crate::bpe::BpeTokenizer;
 use rustc_hash::FxHashMap;
 use serde::{Deserialize, Serialize};
+use std::io::{BufRead, Read};
 use std::sync::OnceLock;
 
 /// Identifier for a vocabulary.
@@ -71,6 +72,27 @@ impl VocabRegistry {
         self.tokenizers.insert(id, tokenizer);
     }
 
+    /// Register `variant_id` as a copy of the tokenizer at `base_id` with
+    /// its special-token surface strings remapped via `renames` (each
+    /// `(old, new)` pair), without retraining or reloading the merge
+    /// table. Returns `false` if `base_id` isn't registered.
+    pub fn register_variant(
+        &mut self,
+        base_id: &VocabId,
+        variant_id: VocabId,
+        renames: &[(&str, &str)],
+    ) -> bool {
+        let Some(base) = self.tokenizers.get(base_id) else {
+            return false;
+        };
+        let mut tokenizer = base.clone();
+        for (old, new) in renames {
+            tokenizer.assign_special(old, new);
+        }
+        self.register(variant_id, tokenizer);
+        true
+    }
+
     /// Get a tokenizer by vocab ID, falling back to default.
     pub fn get(&self, id: &VocabId) -> &BpeTokenizer {
         self.tokenizers
@@ -84,6 +106,13 @@ impl VocabRegistry {
         self.tokenizers.keys().collect()
     }
 
+    /// Iterate over every registered `(VocabId, BpeTokenizer)` pair.
+    /// Crate-internal: used by `search::fuzzy_search` to enumerate
+    /// candidates across all loaded vocabularies.
+    pub(crate) fn entries(&self) -> impl Iterator<Item = (&VocabId, &BpeTokenizer)> {
+        self.tokenizers.iter()
+    }
+
     /// Register the built-in vocabularies.
     ///
     /// These are simplified byte-level BPE tokenizers with common English merges.
@@ -96,6 +125,349 @@ impl VocabRegistry {
         // p50k_base approximation
         self.register(VocabId::p50k(), build_p50k_approx());
     }
+
+    /// Load a tiktoken/HuggingFace-style vocabulary from `vocab.json` +
+    /// `merges.txt` readers and register it under `id`.
+    ///
+    /// `vocab` is a JSON object mapping token surface string → id, using
+    /// the GPT-2 byte-level remapping (printable unicode placeholder
+    /// characters stand in for raw bytes). `merges` is an ordered list of
+    /// `left right` pairs, one per line, with an optional `#version`
+    /// header line that is skipped.
+    pub fn register_from_json(
+        &mut self,
+        id: VocabId,
+        vocab: impl Read,
+        merges: impl Read,
+    ) -> Result<(), VocabLoadError> {
+        let tokenizer = load_bpe_tokenizer(vocab, merges)?;
+        self.register(id, tokenizer);
+        Ok(())
+    }
+
+    /// Convenience wrapper over [`VocabRegistry::register_from_json`] that
+    /// opens `vocab.json` and `merges.txt` by path.
+    pub fn register_from_files(
+        &mut self,
+        id: VocabId,
+        vocab_path: impl AsRef<std::path::Path>,
+        merges_path: impl AsRef<std::path::Path>,
+    ) -> Result<(), VocabLoadError> {
+        let vocab_file = std::fs::File::open(vocab_path)?;
+        let merges_file = std::fs::File::open(merges_path)?;
+        self.register_from_json(id, vocab_file, merges_file)
+    }
+
+    /// Load a token id → raw corpus frequency table (JSON object, e.g.
+    /// `{"1234": 58213, "5678": 4}`) and attach it to the vocab already
+    /// registered under `id`, so `weight_for` reports measured rarity
+    /// instead of the `id / vocab_size` estimate. Frequencies are
+    /// rank-normalized: the most frequent id in the table gets rarity
+    /// `0.0`, the least frequent gets `1.0`. Returns `false` if `id` isn't
+    /// registered yet.
+    pub fn register_rarity_table(
+        &mut self,
+        id: &VocabId,
+        counts: impl Read,
+    ) -> Result<bool, VocabLoadError> {
+        let freq: FxHashMap<u32, u64> = serde_json::from_reader(counts)?;
+        let Some(tokenizer) = self.tokenizers.get_mut(id) else {
+            return Ok(false);
+        };
+        tokenizer.set_rarity_table(rank_normalize_frequencies(&freq));
+        Ok(true)
+    }
+
+    /// Convenience wrapper over [`VocabRegistry::register_rarity_table`]
+    /// that opens the frequency table by path.
+    pub fn register_rarity_table_file(
+        &mut self,
+        id: &VocabId,
+        counts_path: impl AsRef<std::path::Path>,
+    ) -> Result<bool, VocabLoadError> {
+        let counts_file = std::fs::File::open(counts_path)?;
+        self.register_rarity_table(id, counts_file)
+    }
+}
+
+/// Convert raw frequency counts into normalized rarity: the highest-count
+/// id maps to `0.0`, the lowest-count id maps to `1.0`, evenly spaced by
+/// frequency rank in between.
+fn rank_normalize_frequencies(freq: &FxHashMap<u32, u64>) -> FxHashMap<u32, f32> {
+    let mut by_count: Vec<(u32, u64)> = freq.iter().map(|(&id, &count)| (id, count)).collect();
+    by_count.sort_by(|a, b| b.1.cmp(&a.1));
+
+    let n = by_count.len();
+    by_count
+        .into_iter()
+        .enumerate()
+        .map(|(rank, (id, _))| {
+            let rarity = if n <= 1 { 0.0 } else { rank as f32 / (n - 1) as f32 };
+            (id, rarity)
+        })
+        .collect()
+}
+
+/// Errors that can occur while loading a vocabulary from external files.
+#[derive(Debug)]
+pub enum VocabLoadError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Parse(String),
+}
+
+impl std::fmt::Display for VocabLoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VocabLoadError::Io(e) => write!(f, "io error reading vocab files: {e}"),
+            VocabLoadError::Json(e) => write!(f, "malformed vocab.json: {e}"),
+            VocabLoadError::Parse(msg) => write!(f, "malformed merges.txt: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for VocabLoadError {}
+
+impl From<std::io::Error> for VocabLoadError {
+    fn from(e: std::io::Error) -> Self {
+        VocabLoadError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for VocabLoadError {
+    fn from(e: serde_json::Error) -> Self {
+        VocabLoadError::Json(e)
+    }
+}
+
+fn load_bpe_tokenizer(vocab: impl Read, merges: impl Read) -> Result<BpeTokenizer, VocabLoadError> {
+    let vocab_map: FxHashMap<String, u32> = serde_json::from_reader(vocab)?;
+    let byte_table = unicode_to_byte_map();
+
+    let mut encoder: FxHashMap<Vec<u8>, u32> = FxHashMap::default();
+    let mut special_tokens: FxHashMap<String, u32> = FxHashMap::default();
+
+    for (token, id) in &vocab_map {
+        if is_special_surface(token) {
+            special_tokens.insert(token.clone(), *id);
+            continue;
+        }
+        match placeholder_to_bytes(token, &byte_table) {
+            Some(bytes) => {
+                encoder.insert(bytes, *id);
+            }
+            None => {
+                special_tokens.insert(token.clone(), *id);
+            }
+        }
+    }
+
+    let mut merge_list: Vec<(Vec<u8>, Vec<u8>)> = Vec::new();
+    for line in std::io::BufReader::new(merges).lines() {
+        let line = line?;
+        if line.is_empty() || line.starts_with("#version") {
+            continue;
+        }
+        let mut parts = line.split(' ');
+        let left = parts
+            .next()
+            .ok_or_else(|| VocabLoadError::Parse(format!("malformed merge line: {line}")))?;
+        let right = parts
+            .next()
+            .ok_or_else(|| VocabLoadError::Parse(format!("malformed merge line: {line}")))?;
+        let left_bytes = placeholder_to_bytes(left, &byte_table)
+            .ok_or_else(|| VocabLoadError::Parse(format!("unknown merge token: {left}")))?;
+        let right_bytes = placeholder_to_bytes(right, &byte_table)
+            .ok_or_else(|| VocabLoadError::Parse(format!("unknown merge token: {right}")))?;
+        merge_list.push((left_bytes, right_bytes));
+    }
+
+    Ok(BpeTokenizer::new(encoder, merge_list, special_tokens))
+}
+
+fn is_special_surface(token: &str) -> bool {
+    token.starts_with("<|") && token.ends_with("|>")
+}
+
+/// Decode a GPT-2 byte-level placeholder string back to raw bytes, or
+/// `None` if it contains a character outside the remapping (e.g. a
+/// special token that isn't meant to be byte-decoded).
+fn placeholder_to_bytes(s: &str, table: &FxHashMap<char, u8>) -> Option<Vec<u8>> {
+    s.chars().map(|c| table.get(&c).copied()).collect()
+}
+
+/// Build the GPT-2 byte↔unicode remapping: printable bytes map to
+/// themselves, and the remaining (mostly control/whitespace) bytes map to
+/// unused codepoints starting at 256, so every byte has a printable,
+/// round-trippable stand-in character for JSON vocab files.
+fn bytes_to_unicode() -> Vec<(u8, char)> {
+    let mut bytes: Vec<u8> = Vec::with_capacity(256);
+    bytes.extend(b'!'..=b'~');
+    bytes.extend(0xA1u8..=0xACu8);
+    bytes.extend(0xAEu8..=0xFFu8);
+
+    let mut chars: Vec<u32> = bytes.iter().map(|&b| b as u32).collect();
+
+    let mut n = 0u32;
+    for b in 0u32..256 {
+        if !bytes.contains(&(b as u8)) {
+            bytes.push(b as u8);
+            chars.push(256 + n);
+            n += 1;
+        }
+    }
+
+    bytes
+        .into_iter()
+        .zip(chars.into_iter().map(|c| char::from_u32(c).expect("valid codepoint")))
+        .collect()
+}
+
+fn unicode_to_byte_map() -> FxHashMap<char, u8> {
+    bytes_to_unicode().into_iter().map(|(b, c)| (c, b)).collect()
+}
+
+/// Configuration for [`BpeTrainer`].
+#[derive(Debug, Clone)]
+pub struct TrainerConfig {
+    /// Stop training once the tokenizer's vocabulary reaches this size
+    /// (base alphabet + learned merges, not counting special tokens).
+    pub vocab_size: usize,
+    /// Stop training once the best remaining pair count drops below this.
+    pub min_frequency: u64,
+    /// Special tokens to append to the trained vocabulary.
+    pub special_tokens: Vec<String>,
+    /// Base alphabet to seed the encoder with. Defaults to all 256 byte
+    /// values if not given; any byte observed in the corpus that isn't
+    /// in this set is still added so every input remains encodable.
+    pub initial_alphabet: Option<Vec<u8>>,
+}
+
+impl Default for TrainerConfig {
+    fn default() -> Self {
+        Self {
+            vocab_size: 10_000,
+            min_frequency: 2,
+            special_tokens: Vec::new(),
+            initial_alphabet: None,
+        }
+    }
+}
+
+/// Learns BPE merges from a text corpus via the standard count-and-merge
+/// algorithm, producing a real [`BpeTokenizer`] rather than a hand-tuned
+/// approximation.
+pub struct BpeTrainer {
+    config: TrainerConfig,
+}
+
+impl BpeTrainer {
+    pub fn new(config: TrainerConfig) -> Self {
+        Self { config }
+    }
+
+    /// Train a tokenizer on `corpus`, stopping at `vocab_size` or once the
+    /// best remaining merge count falls below `min_frequency`.
+    pub fn train(&self, corpus: &str) -> BpeTokenizer {
+        let cfg = &self.config;
+
+        // Pre-tokenize: whitespace-separated words, tallied by frequency.
+        let mut word_counts: FxHashMap<&str, u64> = FxHashMap::default();
+        for word in corpus.split_whitespace() {
+            *word_counts.entry(word).or_insert(0) += 1;
+        }
+
+        // Seed the base alphabet, extending it with any byte the corpus
+        // actually uses so every word remains representable.
+        let alphabet: Vec<u8> = cfg
+            .initial_alphabet
+            .clone()
+            .unwrap_or_else(|| (0u8..=255).collect());
+
+        let mut encoder: FxHashMap<Vec<u8>, u32> = FxHashMap::default();
+        let mut id_to_bytes: FxHashMap<u32, Vec<u8>> = FxHashMap::default();
+        let mut next_id = 0u32;
+        for b in alphabet {
+            encoder.entry(vec![b]).or_insert_with(|| {
+                let id = next_id;
+                next_id += 1;
+                id
+            });
+        }
+        let mut extra_bytes: Vec<u8> = word_counts
+            .keys()
+            .flat_map(|w| w.bytes())
+            .filter(|b| !encoder.contains_key(&vec![*b]))
+            .collect();
+        extra_bytes.sort_unstable();
+        extra_bytes.dedup();
+        for b in extra_bytes {
+            encoder.insert(vec![b], next_id);
+            next_id += 1;
+        }
+        for (bytes, &id) in &encoder {
+            id_to_bytes.insert(id, bytes.clone());
+        }
+        let byte_to_id: FxHashMap<u8, u32> = encoder
+            .iter()
+            .filter(|(k, _)| k.len() == 1)
+            .map(|(k, &v)| (k[0], v))
+            .collect();
+
+        // Words as symbol-id sequences, in a deterministic (sorted) order.
+        let mut sorted_words: Vec<(&str, u64)> = word_counts.into_iter().collect();
+        sorted_words.sort_unstable_by(|a, b| a.0.cmp(b.0));
+
+        let mut words: Vec<Vec<u32>> = Vec::with_capacity(sorted_words.len());
+        let mut counts: Vec<i64> = Vec::with_capacity(sorted_words.len());
+        for (w, c) in sorted_words {
+            words.push(w.bytes().map(|b| byte_to_id[&b]).collect());
+            counts.push(c as i64);
+        }
+
+        // Count-and-merge core shared with `bpe::train_bpe`: tally
+        // adjacent-pair counts, then merge the globally best pair until
+        // `vocab_size` is reached or the best count drops below
+        // `min_frequency`.
+        let stop_at_next_id = cfg.vocab_size as u32;
+        let min_count = cfg.min_frequency.max(1) as i64;
+        let merges = crate::bpe::run_bpe_merge_loop(
+            &mut words,
+            &counts,
+            &mut encoder,
+            &mut id_to_bytes,
+            &mut next_id,
+            stop_at_next_id,
+            min_count,
+        );
+
+        let mut special_tokens: FxHashMap<String, u32> = FxHashMap::default();
+        for token in &cfg.special_tokens {
+            special_tokens.insert(token.clone(), next_id);
+            next_id += 1;
+        }
+
+        BpeTokenizer::new(encoder, merges, special_tokens)
+    }
+}
+
+/// Convenience wrapper around [`BpeTrainer`]: train a tokenizer on `corpus`
+/// and get back a [`BpeTokenizer`] ready to [`VocabRegistry::register`].
+pub fn train(corpus: &str, config: TrainerConfig) -> BpeTokenizer {
+    BpeTrainer::new(config).train(corpus)
+}
+
+/// Representative context-window size (in tokens) for a vocabulary's model
+/// family, so callers get a live "tokens remaining" guard without having
+/// to look up and pass the number themselves. These are approximate —
+/// actual limits vary by specific model/deployment.
+pub fn known_context_limit(id: &VocabId) -> Option<usize> {
+    match id.as_str() {
+        "cl100k_base" => Some(128_000), // GPT-4 / GPT-4-turbo / GPT-3.5-turbo family
+        "o200k_base" => Some(128_000),  // GPT-4o family
+        "p50k_base" => Some(4_097),     // legacy text-davinci-003
+        _ => None,
+    }
 }
 
 /// Vocabulary metadata for UI display.
@@ -132,12 +504,24 @@ impl VocabRegistry {
 /// Build a cl100k_base-like tokenizer with common English BPE merges.
 /// This is an approximation—for exact results, load the real tiktoken data.
 fn build_cl100k_approx() -> BpeTokenizer {
-    build_english_bpe_tokenizer(COMMON_MERGES_EXTENDED)
+    build_english_bpe_tokenizer(COMMON_MERGES_EXTENDED).with_pre_tokenizer(gpt_pre_tokenizer_pattern())
 }
 
 fn build_o200k_approx() -> BpeTokenizer {
     // o200k uses a larger vocab but similar merge strategy
-    build_english_bpe_tokenizer(COMMON_MERGES_EXTENDED)
+    build_english_bpe_tokenizer(COMMON_MERGES_EXTENDED).with_pre_tokenizer(gpt_pre_tokenizer_pattern())
+}
+
+/// GPT-2-style pre-tokenization pattern: contractions, then leading-space
+/// letter/digit/punctuation runs, then bare whitespace. Real GPT tokenizers
+/// split on this (or a close variant) before ever running BPE merges, so
+/// merges never glue a space onto the wrong side of a word or swallow
+/// digits into neighboring letters.
+fn gpt_pre_tokenizer_pattern() -> regex::Regex {
+    regex::Regex::new(
+        r"'s|'t|'re|'ve|'m|'ll|'d| ?\p{L}+| ?\p{N}+| ?[^\s\p{L}\p{N}]+|\s+",
+    )
+    .expect("static GPT pre-tokenizer pattern is valid")
 }
 
 fn build_p50k_approx() -> BpeTokenizer {
@@ -352,4 +736,210 @@ mod tests {
         assert_eq!(info.id, "cl100k_base");
         assert!(info.vocab_size > 256);
     }
+
+    #[test]
+    fn test_trainer_learns_repeated_word() {
+        let config = TrainerConfig {
+            vocab_size: 300,
+            min_frequency: 2,
+            special_tokens: vec!["<|endoftext|>".to_string()],
+            initial_alphabet: None,
+        };
+        let tok = train("the the the the thing thing thing", config);
+        let tokens = tok.encode("the");
+        // "the" should merge down to a single token after training.
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].text, "the");
+    }
+
+    #[test]
+    fn test_trainer_matches_brute_force_on_word_with_repeated_pair() {
+        // Guards the `BpeTrainer`/`train_bpe` shared merge loop
+        // (`bpe::run_bpe_merge_loop`) against the pair_positions bug fixed
+        // alongside this test: a word where a pair occurs more than once
+        // internally (`"cc"` appears twice in "acbaccc") must not make the
+        // trainer skip re-merging one of those occurrences.
+        fn brute_force_ids(word_bytes: &[u8], stop_at_next_id: u32, min_count: i64) -> Vec<(u32, u32)> {
+            let mut next_id = 256u32;
+            let mut symbols: Vec<u32> = word_bytes.iter().map(|&b| b as u32).collect();
+            let mut merges = Vec::new();
+            while next_id < stop_at_next_id {
+                let mut pair_counts: FxHashMap<(u32, u32), i64> = FxHashMap::default();
+                for w in symbols.windows(2) {
+                    *pair_counts.entry((w[0], w[1])).or_insert(0) += 1;
+                }
+                let best = pair_counts
+                    .iter()
+                    .max_by(|a, b| a.1.cmp(b.1).then_with(|| b.0.cmp(a.0)))
+                    .map(|(&k, &v)| (k, v));
+                let Some((pair, count)) = best else { break };
+                if count < min_count {
+                    break;
+                }
+                let new_id = next_id;
+                next_id += 1;
+                merges.push(pair);
+                let mut i = 0;
+                while i + 1 < symbols.len() {
+                    if symbols[i] == pair.0 && symbols[i + 1] == pair.1 {
+                        symbols[i] = new_id;
+                        symbols.remove(i + 1);
+                    }
+                    i += 1;
+                }
+            }
+            merges
+        }
+
+        let word = "acbaccc";
+        let reference = brute_force_ids(word.as_bytes(), 256 + 5, 1);
+
+        // Apply the reference merges greedily to the word to get the
+        // expected token texts.
+        let mut expected_symbols: Vec<u32> = word.bytes().map(|b| b as u32).collect();
+        let mut expected_id_bytes: FxHashMap<u32, Vec<u8>> =
+            (0u32..256).map(|b| (b, vec![b as u8])).collect();
+        for (new_id, (left, right)) in (256u32..).zip(reference.iter()) {
+            let mut merged = expected_id_bytes[left].clone();
+            merged.extend_from_slice(&expected_id_bytes[right]);
+            expected_id_bytes.insert(new_id, merged);
+            let mut i = 0;
+            while i + 1 < expected_symbols.len() {
+                if expected_symbols[i] == *left && expected_symbols[i + 1] == *right {
+                    expected_symbols[i] = new_id;
+                    expected_symbols.remove(i + 1);
+                }
+                i += 1;
+            }
+        }
+        let expected_texts: Vec<String> = expected_symbols
+            .iter()
+            .map(|id| String::from_utf8(expected_id_bytes[id].clone()).unwrap())
+            .collect();
+
+        let corpus = [word; 5].join(" ");
+        let config = TrainerConfig {
+            vocab_size: 256 + 5,
+            min_frequency: 1,
+            special_tokens: Vec::new(),
+            initial_alphabet: None,
+        };
+        let tok = train(&corpus, config);
+        let actual_texts: Vec<String> = tok.encode(word).iter().map(|t| t.text.clone()).collect();
+
+        assert_eq!(actual_texts, expected_texts);
+    }
+
+    #[test]
+    fn test_trainer_respects_vocab_size() {
+        let config = TrainerConfig {
+            vocab_size: 257, // base alphabet + exactly one merge
+            min_frequency: 1,
+            special_tokens: Vec::new(),
+            initial_alphabet: None,
+        };
+        let tok = train("aaaa aaaa aaaa", config);
+        assert_eq!(tok.vocab_size(), 257);
+    }
+
+    #[test]
+    fn test_trainer_min_frequency_stops_early() {
+        let config = TrainerConfig {
+            vocab_size: 100_000,
+            min_frequency: 1000,
+            special_tokens: Vec::new(),
+            initial_alphabet: None,
+        };
+        // No pair occurs anywhere near 1000 times, so no merges should be learned.
+        let tok = train("hello world", config);
+        assert_eq!(tok.vocab_size(), 256);
+    }
+
+    #[test]
+    fn test_register_from_json() {
+        let vocab_json = br#"{"a":0,"b":1,"ab":2,"<|endoftext|>":3}"#;
+        let merges_txt = b"#version: 0.2\na b\n";
+
+        let mut reg = VocabRegistry::new(VocabId::new("custom"));
+        reg.register_from_json(
+            VocabId::new("custom"),
+            &vocab_json[..],
+            &merges_txt[..],
+        )
+        .unwrap();
+
+        let tok = reg.get(&VocabId::new("custom"));
+        let tokens = tok.encode("ab");
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].id, 2);
+    }
+
+    #[test]
+    fn test_register_variant_remaps_special_tokens() {
+        let mut reg = VocabRegistry::new(VocabId::cl100k());
+        reg.register_builtins();
+
+        let registered = reg.register_variant(
+            &VocabId::cl100k(),
+            VocabId::new("cl100k_chatml"),
+            &[("<|endoftext|>", "<|im_end|>")],
+        );
+        assert!(registered);
+
+        let variant = reg.get(&VocabId::new("cl100k_chatml"));
+        let tokens = variant.encode("<|im_end|>");
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].text, "<|im_end|>");
+    }
+
+    #[test]
+    fn test_register_variant_missing_base() {
+        let mut reg = VocabRegistry::new(VocabId::cl100k());
+        let registered =
+            reg.register_variant(&VocabId::new("nonexistent"), VocabId::new("variant"), &[]);
+        assert!(!registered);
+    }
+
+    #[test]
+    fn test_known_context_limit() {
+        assert_eq!(known_context_limit(&VocabId::cl100k()), Some(128_000));
+        assert_eq!(known_context_limit(&VocabId::new("made_up")), None);
+    }
+
+    #[test]
+    fn test_bytes_to_unicode_roundtrip() {
+        let table = unicode_to_byte_map();
+        let mapping = bytes_to_unicode();
+        for (byte, ch) in mapping {
+            assert_eq!(table[&ch], byte);
+        }
+    }
+
+    #[test]
+    fn test_register_rarity_table() {
+        let mut reg = VocabRegistry::new(VocabId::cl100k());
+        reg.register_builtins();
+
+        // id 0 is by far the most frequent, id 2 never shows up in the corpus
+        let counts = br#"{"0": 100000, "1": 10}"#;
+        let registered = reg
+            .register_rarity_table(&VocabId::cl100k(), &counts[..])
+            .unwrap();
+        assert!(registered);
+
+        let tok = reg.get(&VocabId::cl100k());
+        assert_eq!(tok.rarity_for(0), Some(0.0));
+        assert_eq!(tok.rarity_for(1), Some(1.0));
+        assert_eq!(tok.rarity_for(2), None);
+    }
+
+    #[test]
+    fn test_register_rarity_table_missing_vocab() {
+        let mut reg = VocabRegistry::new(VocabId::cl100k());
+        let counts = br#"{"0": 1}"#;
+        let registered = reg
+            .register_rarity_table(&VocabId::new("nonexistent"), &counts[..])
+            .unwrap();
+        assert!(!registered);
+    }
 }
@@ -3,7 +3,11 @@
 //! Thin layer exposing the Rust core to JavaScript via wasm-bindgen.
 //! Designed for <token-viz> web component consumption.
 
-use prompt_quant_core::{TokenCategory, TokenColorMap, VocabId, VocabRegistry};
+use prompt_quant_core::{
+    BpeTokenizer, IncrementalTokenizer, TokenCategory, TokenColorMap, TokenColorMapConfig,
+    VocabId, VocabRegistry,
+};
+use std::sync::Arc;
 use wasm_bindgen::prelude::*;
 
 /// Initialize the WASM module (call once on load).
@@ -43,15 +47,20 @@ pub fn tokenize(text: &str, vocab_id: &str) -> JsValue {
 }
 
 /// Stateful incremental tokenizer for real-time keystroke use.
-/// Holds internal state between calls for efficient diff-based updates.
+///
+/// Backed by a real `IncrementalTokenizer` (not just a whole-string cache),
+/// so `update` returns the `changed_range` the partial-reparse machinery
+/// computes — the front end can repaint just the affected token nodes
+/// instead of every node on every keystroke.
 #[wasm_bindgen]
 pub struct WasmIncrementalTokenizer {
-    // We can't hold a reference to the global registry's tokenizer across WASM calls
-    // easily, so we store the vocab_id and do a lookup each time.
-    // The cost is negligible compared to the tokenization itself.
-    vocab_id: String,
-    last_input: String,
-    last_result_json: String,
+    inner: IncrementalTokenizer,
+}
+
+fn load_tokenizer(vocab_id: &str) -> Arc<BpeTokenizer> {
+    let reg = VocabRegistry::global();
+    let id = VocabId::new(vocab_id);
+    Arc::new(reg.get(&id).clone())
 }
 
 #[wasm_bindgen]
@@ -60,65 +69,86 @@ impl WasmIncrementalTokenizer {
     #[wasm_bindgen(constructor)]
     pub fn new(vocab_id: &str) -> Self {
         Self {
-            vocab_id: vocab_id.to_string(),
-            last_input: String::new(),
-            last_result_json: String::new(),
+            inner: IncrementalTokenizer::new(load_tokenizer(vocab_id), vocab_id),
         }
     }
 
-    /// Update with new input text. Returns the tokenization result.
-    /// Internally caches to avoid redundant work on identical inputs.
+    /// Update with new input text. Returns `{ result, changed_range }`,
+    /// where `changed_range` is `[start, end]` token indices or `null`.
     pub fn update(&mut self, input: &str) -> JsValue {
-        if input == self.last_input && !self.last_result_json.is_empty() {
-            // Parse cached JSON back to JsValue
-            if let Ok(cached) = serde_json::from_str::<serde_json::Value>(&self.last_result_json) {
-                return serde_wasm_bindgen::to_value(&cached).unwrap_or(JsValue::NULL);
-            }
-        }
-
-        let id = VocabId::new(&self.vocab_id);
-        let result = prompt_quant_core::tokenize(input, &id);
-
-        // Cache the result
-        self.last_input = input.to_string();
-        self.last_result_json = serde_json::to_string(&result).unwrap_or_default();
-
-        serde_wasm_bindgen::to_value(&result).unwrap_or(JsValue::NULL)
+        let update = self.inner.update(input);
+        serde_wasm_bindgen::to_value(&update).unwrap_or(JsValue::NULL)
     }
 
     /// Switch to a different vocabulary. Clears cached state.
     #[wasm_bindgen(js_name = setVocab)]
     pub fn set_vocab(&mut self, vocab_id: &str) {
-        self.vocab_id = vocab_id.to_string();
-        self.last_input.clear();
-        self.last_result_json.clear();
+        self.inner = IncrementalTokenizer::new(load_tokenizer(vocab_id), vocab_id);
     }
 
     /// Get the current vocabulary ID.
     #[wasm_bindgen(js_name = getVocab)]
     pub fn get_vocab(&self) -> String {
-        self.vocab_id.clone()
+        self.inner.vocab_id().to_string()
     }
 
     /// Reset internal state (forces full re-tokenization on next update).
     pub fn reset(&mut self) {
-        self.last_input.clear();
-        self.last_result_json.clear();
+        self.inner.reset();
+    }
+
+    /// Re-sync to whatever theme `setTheme` last set as active, so an
+    /// already-running incremental tokenizer picks up a palette switch
+    /// without losing its cached prefix/suffix state.
+    #[wasm_bindgen(js_name = setTheme)]
+    pub fn set_theme(&mut self) {
+        self.inner.sync_theme();
     }
 }
 
-/// Convenience: get the category name for a token (for CSS class mapping).
+/// Highlight every match of `query` (substring or regex) in `text`,
+/// reporting the char/byte span and overlapping token index range for
+/// each match plus an alternating highlight segmentation.
+#[wasm_bindgen(js_name = highlightMatches)]
+pub fn highlight_matches(text: &str, vocab_id: &str, query: &str) -> JsValue {
+    let id = VocabId::new(vocab_id);
+    let result = prompt_quant_core::tokenize(text, &id);
+    let highlight = prompt_quant_core::highlight_in_result(text, &result, query);
+    serde_wasm_bindgen::to_value(&highlight).unwrap_or(JsValue::NULL)
+}
+
+/// Fuzzy-search every loaded vocabulary for tokens matching `query`.
+/// Returns up to `limit` `TokenSearchResult`s sorted by descending score.
+#[wasm_bindgen(js_name = fuzzySearch)]
+pub fn fuzzy_search(query: &str, limit: usize) -> JsValue {
+    let reg = VocabRegistry::global();
+    let results = reg.fuzzy_search(query, limit);
+    serde_wasm_bindgen::to_value(&results).unwrap_or(JsValue::NULL)
+}
+
+/// Convenience: get the category name for a token (for CSS class mapping),
+/// using the current active theme's category rules.
 #[wasm_bindgen(js_name = tokenCategory)]
 pub fn token_category(token_id: u32, token_text: &str) -> String {
-    let color_map = TokenColorMap::default();
+    let color_map = TokenColorMap::active();
     let cat = color_map.categorize(token_id, token_text);
     format!("{:?}", cat).to_lowercase()
 }
 
-/// Convenience: get the RGB color for a category name.
+/// Convenience: get the Unicode script name for a token's text (for CSS
+/// class mapping alongside `tokenCategory`).
+#[wasm_bindgen(js_name = tokenScript)]
+pub fn token_script(token_text: &str) -> String {
+    let color_map = TokenColorMap::active();
+    let script = color_map.script_for(token_text);
+    format!("{:?}", script).to_lowercase()
+}
+
+/// Convenience: get the RGB color for a category name, using the current
+/// active theme's palette.
 #[wasm_bindgen(js_name = categoryColor)]
 pub fn category_color(category: &str) -> JsValue {
-    let color_map = TokenColorMap::default();
+    let color_map = TokenColorMap::active();
     let cat = match category {
         "whitespace" => TokenCategory::Whitespace,
         "punctuation" => TokenCategory::Punctuation,
@@ -133,3 +163,37 @@ pub fn category_color(category: &str) -> JsValue {
     let color = color_map.color_for(&cat);
     serde_wasm_bindgen::to_value(&color).unwrap_or(JsValue::NULL)
 }
+
+/// Switch the active color theme at runtime. `theme` is either the name
+/// of a built-in theme (`"frost_glass"`, `"solarized_dark"`,
+/// `"high_contrast"`) or a `TokenColorMapConfig`-shaped JS object for a
+/// fully custom theme. Returns `false` (and leaves the active theme
+/// unchanged) if `theme` is neither.
+///
+/// Already-running `WasmIncrementalTokenizer` instances keep their old
+/// theme until their own `setTheme()` is called.
+#[wasm_bindgen(js_name = setTheme)]
+pub fn set_theme(theme: JsValue) -> bool {
+    if let Some(name) = theme.as_string() {
+        if let Some(map) = TokenColorMap::named(&name) {
+            TokenColorMap::set_active(map);
+            return true;
+        }
+        return false;
+    }
+
+    match serde_wasm_bindgen::from_value::<TokenColorMapConfig>(theme) {
+        Ok(config) => {
+            TokenColorMap::set_active(TokenColorMap::from_config(config));
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// List the built-in theme names accepted by `setTheme`.
+#[wasm_bindgen(js_name = listThemes)]
+pub fn list_themes() -> JsValue {
+    let themes = ["frost_glass", "solarized_dark", "high_contrast"];
+    serde_wasm_bindgen::to_value(&themes).unwrap_or(JsValue::NULL)
+}